@@ -1,109 +1,339 @@
 use std::collections::{HashSet, HashMap};
-use std::fmt::format;
 
-use crate::parser::node::{Node, Literal};
+use crate::parser::node::{Node, Literal, InterpolationPart};
 use crate::parser::signature_parser::TypedSignature;
-use crate::parser::types::type_env::{TypeEnv, self, TypeBindings};
 
+/// The name of a function together with the ones it is nested in, e.g. `outer$inner`.
+/// Functions inside function values are under their index, e.g. `outer$0$inner`
 pub type FuncID = String;
 
+/// A named function which is called directly, it is compiled once
+/// for every signature it is used with
 pub struct FuncInfo {
-    pub id: FuncID,
     pub sig: TypedSignature,
     pub nodes: Vec<Node>,
-    pub instances: HashSet<TypedSignature>,
-    pub captures_vars: HashSet<String>,
-    pub calls: Vec<(FuncID, TypedSignature)>
+    pub instances: HashSet<TypedSignature>
 }
 
+/// The names a function uses before assigning them itself, in the order of their
+/// first use. Only the ones which are variables where the function is created are
+/// actually captured, plain functions are called directly
+pub fn free_names(nodes: &[Node]) -> Vec<String> {
+    let mut free = Vec::new();
+    collect_free_names(nodes, &mut HashSet::new(), &mut free);
+
+    free
+}
+
+fn collect_free_names(nodes: &[Node], locals: &mut HashSet<String>, free: &mut Vec<String>) {
+    let add = |name: &String, locals: &HashSet<String>, free: &mut Vec<String>| {
+        if !locals.contains(name) && !free.contains(name) {
+            free.push(name.clone())
+        }
+    };
+
+    for node in nodes {
+        match node {
+            Node::Assigment { name, .. } => {
+                locals.insert(name.clone());
+            },
+
+            Node::Variable { name, .. } | Node::Call { name, .. } => 
+                add(name, locals, free),
+
+            // What a nested function captures has to be captured by this one too
+            Node::Literal { value: Literal::Function(_, body), .. } => {
+                for name in free_names(body) {
+                    add(&name, locals, free)
+                }
+            },
+
+            Node::Literal { value: Literal::List(body) | Literal::Tuple(body), .. } => 
+                collect_free_names(body, locals, free),
+
+            Node::Literal { value: Literal::Interpolation(parts), .. } => {
+                for part in parts {
+                    if let InterpolationPart::Code(body) = part {
+                        collect_free_names(body, &mut locals.clone(), free)
+                    }
+                }
+            },
+
+            Node::Match { arms, .. } => {
+                for arm in arms {
+                    collect_free_names(arm, &mut locals.clone(), free)
+                }
+            },
+
+            _ => ()
+        }
+    }
+}
+
+/// The named functions assigned in a scope which have to be closures: the ones using one 
+/// of its variables or another closure. The rest is compiled into plain functions
+pub fn named_closures<F>(nodes: &[Node], is_variable: F) -> HashSet<String> 
+    where F: Fn(&str) -> bool
+{
+    let named: Vec<(&String, Vec<String>)> = nodes
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Node::Literal { value: Literal::Function(_, body), .. }, Node::Assigment { name, .. }] => 
+                Some((name, free_names(body))),
+
+            _ => None
+        })
+        .collect();
+
+    let assigned: HashSet<&String> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Assigment { name, .. } if named.iter().all(|(func, _)| *func != name) => Some(name),
+
+            _ => None
+        })
+        .collect();
+
+    let mut closures = HashSet::new();
+
+    // Calling a closure makes a function a closure too
+    loop {
+        let known = closures.len();
+
+        for (name, free) in &named {
+            let captures = free
+                .iter()
+                .any(|var| var != *name && (is_variable(var) || assigned.contains(var) || closures.contains(var)));
+
+            if captures {
+                closures.insert(name.to_string());
+            }
+        }
+
+        if closures.len() == known {
+            return closures
+        }
+    }
+}
+
+/// The named functions of all programs translated so far, which are taken out of their
+/// AST. Closures stay where they are, as they are created with the values they capture
+#[derive(Default)]
 pub struct CodeGraphBuilder {
-    funcs: Vec<FuncInfo>
+    pub funcs: HashMap<FuncID, FuncInfo>,
+
+    /// The top level functions, later programs can use them too, e.g. in the REPL
+    globals: HashMap<String, FuncID>
 }
 
 impl CodeGraphBuilder {
+    /// Moves the named functions into the graph, the words using them are renamed to their ids
+    pub fn analyze_program(&mut self, nodes: Vec<Node>) -> Vec<Node> {
+        let mut scoped_names = self.globals.clone();
+        let nodes = self.analyze("", nodes, &HashSet::new(), &mut scoped_names);
+
+        self.globals = scoped_names;
+
+        nodes
+    }
+
     fn analyze(
         &mut self,
-        id: FuncID,
-        sig: TypedSignature,
-        nodes: &Vec<Node>,
-        bindings: TypeBindings,
-        mut scoped_names: HashMap<String, String>
-    ) {
-        let mut local_vars = HashSet::new();
-        let mut captures_vars = HashSet::new();
-
-        let mut calls = Vec::new();
-        let mut env = TypeEnv::new(&bindings);
+        id: &str,
+        nodes: Vec<Node>,
+        variables: &HashSet<String>,
+        scoped_names: &mut HashMap<String, FuncID>
+    ) -> Vec<Node> {
+        let closures = named_closures(&nodes, |name| variables.contains(name));
+
+        let mut funcs = HashMap::new();
+
+        // The functions of a scope can call each other, no matter where they are defined
+        for pair in nodes.windows(2) {
+            if let [Node::Literal { value: Literal::Function(sig, _), .. }, Node::Assigment { name, .. }] = pair {
+                if !closures.contains(name) {
+                    let func_id = self.declare(id, name, sig.clone());
+
+                    scoped_names.insert(name.clone(), func_id.clone());
+                    funcs.insert(name.clone(), func_id);
+                }
+            }
+        }
+
+        let mut variables = variables.clone();
+
+        variables.extend(nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Assigment { name, .. } if !funcs.contains_key(name) => Some(name.clone()),
 
+                _ => None
+            }));
+
+        let mut analyzed = Vec::new();
         let mut closure_idx = 0;
+        let mut nodes = nodes.into_iter().peekable();
 
-        for node in nodes {
-            match node {
-                Node::Assigment { name, .. } => {
-                    scoped_names.insert(name.to_owned(), format!("{id}${name}")); // WRONG
-                    local_vars.insert(name);
-                    node.apply(&mut env).unwrap();
-                },
+        while let Some(node) = nodes.next() {
+            let node = match node {
+                Node::Literal { value: Literal::Function(sig, body), typ, token } => {
+                    match nodes.peek() {
+                        Some(Node::Assigment { name, .. }) if funcs.contains_key(name) => {
+                            let func_id = funcs[name].clone();
+                            let body = self.analyze(&func_id, body, &variables, &mut scoped_names.clone());
 
-                Node::Variable { name, .. } => {
-                    if !local_vars.contains(name){
-                        captures_vars.insert(name.to_string());
-                    }
-                    node.apply(&mut env).unwrap();
-                },
+                            self.funcs.get_mut(&func_id).unwrap().nodes = body;
 
-                Node::Call { name, arguments, returns, .. } => {
-                    let actual_args = env.stack.clone_top(arguments.len());
+                            // The function is called directly, it is not assigned to a variable
+                            nodes.next();
+                            continue;
+                        },
 
-                    node.apply(&mut env).unwrap();
+                        _ => {
+                            let body = self.analyze(&format!("{id}${closure_idx}"), body, &variables, &mut scoped_names.clone());
+                            closure_idx += 1;
 
-                    let actual_returns = env.stack.clone_top(returns.len());
+                            Node::Literal { value: Literal::Function(sig, body), typ, token }
+                        }
+                    }
+                },
 
-                    calls.push((
-                        scoped_names.get(name).unwrap().clone(),
-                        TypedSignature::new(actual_args.into(), actual_returns.into())));
+                // A variable hides the functions with its name from now on
+                Node::Assigment { ref name, .. } => {
+                    scoped_names.remove(name);
+                    node
                 },
 
-                Node::Literal { value: Literal::Function(sig, nodes), .. } => {
-                    self.analyze(
-                        format!("{id}${closure_idx}"), 
-                        sig.clone(),
-                        nodes, 
-                        env.bindings.clone(),
-                        scoped_names.clone()
-                    );
+                Node::Variable { name, token, typ } => Node::Variable { 
+                    name: scoped_names.get(&name).cloned().unwrap_or(name), 
+                    token, 
+                    typ 
+                },
 
-                    closure_idx += 1;
+                Node::Call { name, token, arguments, returns, constraints } => Node::Call { 
+                    name: scoped_names.get(&name).cloned().unwrap_or(name), 
+                    token, 
+                    arguments, 
+                    returns, 
+                    constraints 
+                },
 
-                    node.apply(&mut env).unwrap();
-                }
+                node => self.analyze_nested(id, node, &variables, scoped_names)
+            };
 
-                Node::Literal { .. } => {
-                    node.apply(&mut env).unwrap();
-                }
-            }
+            analyzed.push(node);
         }
 
+        analyzed
+    }
 
-        let info = FuncInfo {
-            id,
-            sig,
-            nodes: nodes.to_vec(),
-            instances: HashSet::new(),
-            captures_vars,
-            calls
+    /// The bodies of branches, loops and literals are scopes of their own
+    fn analyze_nested(&mut self, id: &str, node: Node, variables: &HashSet<String>, scoped_names: &HashMap<String, FuncID>) -> Node {
+        let mut nested = |nodes: Vec<Node>| self.analyze(id, nodes, variables, &mut scoped_names.clone());
+
+        match node {
+            Node::Literal { value: Literal::List(body), typ, token } => 
+                Node::Literal { value: Literal::List(nested(body)), typ, token },
+
+            Node::Literal { value: Literal::Tuple(body), typ, token } => 
+                Node::Literal { value: Literal::Tuple(nested(body)), typ, token },
+
+            Node::Literal { value: Literal::Interpolation(parts), typ, token } => {
+                let parts = parts
+                    .into_iter()
+                    .map(|part| match part {
+                        InterpolationPart::Code(body) => InterpolationPart::Code(nested(body)),
+
+                        text => text
+                    })
+                    .collect();
+
+                Node::Literal { value: Literal::Interpolation(parts), typ, token }
+            },
+
+            Node::Match { union, arms, token, arguments, returns } => 
+                Node::Match { union, arms: arms.into_iter().map(nested).collect(), token, arguments, returns },
+
+            node => node
+        }
+    }
+
+    /// Functions with the same name in different scopes get different ids, 
+    /// the same goes for functions defined again by later programs
+    fn declare(&mut self, scope: &str, name: &str, sig: TypedSignature) -> FuncID {
+        let base = if scope.is_empty() {
+            name.to_string()
+        }
+        else {
+            format!("{scope}${name}")
         };
 
-        todo!()
+        let mut func_id = base.clone();
+        let mut i = 0;
+
+        while self.funcs.contains_key(&func_id) {
+            i += 1;
+            func_id = format!("{base}${i}");
+        }
+
+        self.funcs.insert(func_id.clone(), FuncInfo { 
+            sig, 
+            nodes: Vec::new(), 
+            instances: HashSet::new() 
+        });
+
+        func_id
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+
+    use crate::{lexer::lex, source::add_source, parser::{parse_program, node::Node}, stdlib::create_stdlib};
+
+    use super::CodeGraphBuilder;
+
+    fn analyze(graph: &mut CodeGraphBuilder, src: &str) -> Vec<Node> {
+        let mut type_env = create_stdlib::<JITModule>().type_env();
+        let tokens = lex(add_source("test", src.to_string())).unwrap();
+        let nodes = parse_program(tokens, &mut type_env).unwrap();
+
+        graph.analyze_program(nodes)
+    }
+
+    #[test]
+    fn ids_are_distinct_per_scope() {
+        let mut graph = CodeGraphBuilder::default();
+
+        assert_eq!(graph.declare("", "f", "( -- )".parse().unwrap()), "f");
+        assert_eq!(graph.declare("outer", "inner", "( -- )".parse().unwrap()), "outer$inner");
+        assert_eq!(graph.declare("outer$0", "inner", "( -- )".parse().unwrap()), "outer$0$inner");
 
-// Task:
-// - Find what variables a function captures / if it's a closure
-//   - A function captures all variables which are used but not declared in it
-//   - A single exception are non-closure functions, which are never captured
-//
-// - Find with which types a function is called
-//
-// - Mangle function names (needed if two function with equal names exist in different scopes)
\ No newline at end of file
+        // Defined again
+        assert_eq!(graph.declare("", "f", "( num -- num )".parse().unwrap()), "f$1");
+        assert_eq!(graph.declare("", "f", "( -- )".parse().unwrap()), "f$2");
+        assert_eq!(graph.declare("outer", "inner", "( -- )".parse().unwrap()), "outer$inner$1");
+    }
+
+    #[test]
+    fn functions_are_renamed_to_their_ids() {
+        let mut graph = CodeGraphBuilder::default();
+
+        analyze(&mut graph, "outer: ( num -- num ) { call { helper helper: ( num -- num ) { } } inner inner: ( num -- num ) { } }");
+
+        let mut ids: Vec<&String> = graph.funcs.keys().collect();
+        ids.sort();
+
+        assert_eq!(ids, ["outer", "outer$0$helper", "outer$inner"]);
+
+        // A later program, e.g. the next line in the REPL, calls the new function
+        let nodes = analyze(&mut graph, "f: ( num -- num ) { }\nf 1");
+
+        assert!(matches!(&nodes[..], [_, Node::Call { name, .. }] if name == "f"));
+
+        let nodes = analyze(&mut graph, "f: ( int -- int ) { }\nf 1i");
+
+        assert!(matches!(&nodes[..], [_, Node::Call { name, .. }] if name == "f$1"));
+    }
+}
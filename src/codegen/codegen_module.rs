@@ -1,21 +1,48 @@
+use std::{rc::Rc, collections::HashMap};
+
 use cranelift::prelude::*;
-use cranelift_module::{Module, DataContext, DataId, FuncId, FuncOrDataId, FunctionDeclaration};
+use cranelift_module::{Module, DataContext, DataId, FuncId, FuncOrDataId};
 
+use crate::code_graph::CodeGraphBuilder;
 use crate::error::{Error, error};
 use crate::parser::signature_parser::TypedSignature;
 use crate::parser::node::Node;
-use crate::stdlib::library::Transformations;
+use crate::parser::types::{typ::Type, typelist::TypeList, record::Record, tagged_union::Union, class::Instance};
+use crate::stdlib::{library::Transformations, records::RecordWords, unions::UnionConstructors, instances::InstanceMethods};
 
-use super::function_translator::{FunctionTranslator, TranslatedFunction};
+use super::function_translator::{FunctionTranslator, TranslatedFunction, ClosureTemplate};
 
 pub const MANGLE_PREFIX: &str = "ez";
 
+/// The symbol of a named function compiled for the given signature, 
+/// e.g. `ez$outer$inner$num,int$num`
+pub fn mangle(id: &str, sig: &TypedSignature) -> String {
+    let types = |list: &TypeList| list
+        .iter()
+        .map(|typ| typ.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!("{MANGLE_PREFIX}${id}${}${}", types(sig.arguments()), types(sig.returns()))
+}
+
 pub struct CodeGenModule<M: Module> {
     data_ctx: DataContext,
 
     pub module: M,
 
-    pub transformations: Transformations<M>
+    pub transformations: Transformations<M>,
+
+    /// The functions assigned to a name, which are called directly. They are
+    /// compiled when they are used, once for every signature
+    pub code_graph: CodeGraphBuilder,
+
+    /// The functions taking the closure environment which call a plain 
+    /// function, used when the function is pushed as a value
+    pub closure_adapters: HashMap<FuncId, FuncId>,
+
+    /// The function values, which are compiled once for every signature they are used with
+    pub closure_templates: Vec<ClosureTemplate>
 }
 
 impl<M: Module> CodeGenModule<M> {
@@ -23,16 +50,36 @@ impl<M: Module> CodeGenModule<M> {
         CodeGenModule {
             data_ctx: DataContext::new(),
             transformations: Vec::new(),
+            code_graph: CodeGraphBuilder::default(),
+            closure_adapters: HashMap::new(),
+            closure_templates: Vec::new(),
             module
         }
     }
 
-    pub fn translate_ast(&mut self, sig: TypedSignature, nodes: Vec<Node>) -> Result<TranslatedFunction<M>, Error> {
+    pub fn translate_ast(&mut self, sig: TypedSignature, nodes: Vec<Node>) -> Result<TranslatedFunction<'_, M>, Error> {
+        let nodes = self.code_graph.analyze_program(nodes);
+
         FunctionTranslator::new(self)
             .with_signature(sig)
             .with_body(nodes)
     }
 
+    /// The words of the record are translated from now on
+    pub fn declare_record(&mut self, record: Record) {
+        self.transformations.push(Rc::new(RecordWords::new(record)));
+    }
+
+    /// The constructors of the union are translated from now on
+    pub fn declare_union(&mut self, union: Union) {
+        self.transformations.push(Rc::new(UnionConstructors::new(union)));
+    }
+
+    /// The methods of the instance are translated from now on
+    pub fn declare_instance(&mut self, instance: Instance) {
+        self.transformations.push(Rc::new(InstanceMethods::new(instance)));
+    }
+
     pub fn create_data(&mut self, content: Vec<u8>) -> Result<DataId, Error> {
         self.data_ctx.define(content.into_boxed_slice());
 
@@ -63,6 +110,23 @@ impl<M: Module> CodeGenModule<M> {
     }
 
     pub fn build_cranelift_signature(&self, sig: &TypedSignature) -> Result<Signature, Error> {
+        // Only the values above a shared row variable are actually passed
+        if sig.arguments().row() != sig.returns().row() {
+            return Err(error(format!(
+                "Functions with different row variables {} cannot be compiled yet", 
+                sig.generalize()
+            )));
+        }
+
+        let is_generic = sig.arguments()
+            .iter()
+            .chain(sig.returns().iter())
+            .any(|typ| matches!(typ, Type::Variable(..)));
+
+        if is_generic {
+            return Err(error(format!("Generic functions {} cannot be compiled yet", sig.generalize())));
+        }
+
         let mut cranelift_cig = self.module.make_signature();
     
         let params: Vec<AbiParam> = sig.arguments().clone().into();
@@ -73,4 +137,41 @@ impl<M: Module> CodeGenModule<M> {
 
         Ok(cranelift_cig)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::parser::signature_parser::TypedSignature;
+
+    use super::mangle;
+
+    fn sig(src: &str) -> TypedSignature {
+        src.parse().unwrap()
+    }
+
+    #[test]
+    fn mangled_names() {
+        assert_eq!(mangle("f", &sig("( num int -- str )")), "ez$f$num,int$str");
+        assert_eq!(mangle("outer$inner", &sig("( list[num] -- )")), "ez$outer$inner$list[num]$");
+    }
+
+    #[test]
+    fn instances_have_distinct_symbols() {
+        let instances = [
+            mangle("f", &sig("( num -- num )")),
+            mangle("f", &sig("( int -- int )")),
+            mangle("f", &sig("( num num -- )")),
+            mangle("f", &sig("( -- num num )")),
+            mangle("f", &sig("( list[num] -- )")),
+            mangle("f", &sig("( list[int] -- )")),
+            mangle("f$1", &sig("( num -- num )")),
+            mangle("g", &sig("( num -- num )")),
+            mangle("g$f", &sig("( num -- num )")),
+        ];
+
+        for (i, a) in instances.iter().enumerate() {
+            for b in &instances[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
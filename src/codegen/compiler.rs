@@ -3,7 +3,7 @@ use std::{path::PathBuf, fs};
 use cranelift_module::Module;
 use cranelift_object::{ObjectModule, ObjectBuilder};
 
-use crate::{parser::{types::type_env::TypeEnv, parse, node::Node, signature_parser::TypedSignature}, lexer::lex, error::{Error, error}, config::{CompilationConfig, DebugConfig}, debug_printer::*, stdlib::create_stdlib};
+use crate::{parser::{types::type_env::TypeEnv, parse_program, node::Node, signature_parser::TypedSignature}, lexer::lex, source::{SourceId, add_source}, error::{Error, error}, config::{CompilationConfig, DebugConfig}, debug_printer::*, stdlib::create_stdlib};
 
 use super::{codegen_module::CodeGenModule, external_linker::link, success, fail, function_translator::FunctionOptions, native_isa};
 
@@ -39,13 +39,13 @@ impl Compiler {
     pub fn compile_file(self, config: &CompilationConfig, debug_config: &DebugConfig) {
         let (input_file, output_file) = extract_file_paths(config);
 
-        let src = match fs::read_to_string(input_file) {
-            Ok(src) => src,
+        let source = match fs::read_to_string(&input_file) {
+            Ok(src) => add_source(input_file.display(), src),
             
-            Err(err) => fail(error(err), "".to_string())
+            Err(err) => fail(error(err))
         };
 
-        let compilation_result = self.do_compile(src.clone(), &output_file, debug_config);
+        let compilation_result = self.do_compile(source, &output_file, debug_config);
 
         let result = compilation_result
             .and_then(|_| link(&output_file, &config.linkage))
@@ -61,15 +61,15 @@ impl Compiler {
         match result {
             Ok(_) => success(),
 
-            Err(err) => fail(err, src),
+            Err(err) => fail(err),
         }
     }
 
-    fn do_compile(mut self, src: String, outfile: &PathBuf, debug_config: &DebugConfig) -> Result<(), Error> {
-        let tokens = lex(src)?;
+    fn do_compile(mut self, source: SourceId, outfile: &PathBuf, debug_config: &DebugConfig) -> Result<(), Error> {
+        let tokens = lex(source)?;
         debug_tokens(&tokens, debug_config);
 
-        let ast = parse(tokens, &mut self.type_env)?;
+        let ast = parse_program(tokens, &mut self.type_env)?;
         debug_ast(&ast, debug_config);
 
         let options = FunctionOptions::internal();
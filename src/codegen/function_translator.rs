@@ -1,16 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use cranelift::{prelude::{FunctionBuilder, Value, InstBuilder, FunctionBuilderContext, isa::{CallConv, TargetFrontendConfig}, MemFlags}, codegen::Context};
+use cranelift::{prelude::{FunctionBuilder, Value, InstBuilder, FunctionBuilderContext, isa::{CallConv, TargetFrontendConfig}, MemFlags, Block, TrapCode, IntCC, AbiParam}, codegen::Context, frontend::Switch};
 use cranelift_module::{Module, Linkage, FuncId};
 
-use crate::{parser::{node::{Node, Literal}, types::{typ::Type, self}, signature_parser::TypedSignature}, error::{Error, error}};
+use crate::{code_graph::free_names, parser::{node::{Node, Literal, InterpolationPart}, types::{typ::Type, self, typelist::TypeList, tagged_union::Union, substitution::Substitution, type_arguments}, signature_parser::TypedSignature}, error::{Error, error}};
 
-use super::{pointer_type, codegen_module::CodeGenModule};
+use super::{pointer_type, codegen_module::{CodeGenModule, mangle}};
 
 // Layout of Types:
 // num - just a f64
+// int - just an i64
+// bool - an i8, either 0 or 1
 // str - pointer to a struct: <len:i64><content:&[u8]><0:u8>
-// 
+// record - pointer to its fields, each one occupying 8 bytes
+// union - pointer to a struct: <tag:i64><payload, each value occupying 8 bytes>
+// fun - pointer to a closure record: <code:pointer><captured values, each one occupying 8 bytes>,
+//       the code takes the record as its last argument
 
 pub struct FunctionOptions {
     call_conv: CallConv,
@@ -41,7 +46,7 @@ pub struct TranslatedFunction<'a, M: Module> {
 
 impl<'a, M: Module> TranslatedFunction<'a, M> {
     pub fn finish_func(mut self, name: &str, options: FunctionOptions) -> Result<(FuncId, Context), Error> {
-        let mut sig = &mut self.context.func.signature;
+        let sig = &mut self.context.func.signature;
         sig.call_conv = options.call_conv;
 
         let id = self
@@ -57,8 +62,20 @@ impl<'a, M: Module> TranslatedFunction<'a, M> {
         Ok((id, self.context))
     }
 
+    /// Defines a function which was declared before, e.g. a named one
+    pub fn finish_declared_func(mut self, id: FuncId, options: FunctionOptions) -> Result<(FuncId, Context), Error> {
+        self.context.func.signature.call_conv = options.call_conv;
+
+        self
+            .codegen
+            .module
+            .define_function(id, &mut self.context)?;
+
+        Ok((id, self.context))
+    }
+
     pub fn finish_anon_func(mut self, options: FunctionOptions) -> Result<(FuncId, Context), Error> {
-        let mut sig = &mut self.context.func.signature;
+        let sig = &mut self.context.func.signature;
         sig.call_conv = options.call_conv;
 
         let id = self
@@ -75,13 +92,61 @@ impl<'a, M: Module> TranslatedFunction<'a, M> {
     }
 }
 
+/// A function compiled from a function value, which loads the variables it 
+/// captures from the closure record passed as its last argument
+#[derive(Clone, Default)]
+pub struct Closure {
+    /// A named closure calls itself through its own record
+    pub name: Option<String>,
+
+    pub captures: Vec<(String, cranelift::prelude::Type)>
+}
+
+/// A function value, which is compiled when it is used, once for every signature
+pub struct ClosureTemplate {
+    pub sig: TypedSignature,
+
+    pub body: Vec<Node>,
+
+    pub closure: Closure,
+
+    /// The templates of the captured closures and of the closure itself
+    pub closures: HashMap<String, usize>,
+
+    pub instances: HashMap<TypedSignature, FuncId>
+}
+
+impl ClosureTemplate {
+    /// A closure which is not generic is compiled once, when it is created
+    fn is_generic(&self) -> bool {
+        !instance_signature(&self.sig, &self.sig).1.variables().is_empty()
+    }
+}
+
+/// What decides whether the body of a loop runs again
+pub enum LoopControl<'t> {
+    /// Runs the body as often as the int says
+    Times(Value),
+
+    /// Runs the body for every element of the list with the given type, the element is on top
+    Each(Value, &'t Type),
+
+    /// Runs the body as long as the closure with the given type leaves true on top
+    While(Value, &'t Type)
+}
+
 pub struct FunctionTranslator<'a, M: Module> {
     pub codegen: &'a mut CodeGenModule<M>,
 
     pub signature: TypedSignature,
 
+    pub closure: Option<Closure>,
+
     pub variables: HashMap<String, Value>,
 
+    /// The variables which are closure records, with their templates
+    pub closures: HashMap<String, usize>,
+
     pub stack: Vec<Value>
 }
 
@@ -92,7 +157,9 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         FunctionTranslator { 
             codegen,
             signature: TypedSignature::default(),
+            closure: None,
             variables: HashMap::new(),
+            closures: HashMap::new(),
             stack: Vec::new()
         }
     }
@@ -102,6 +169,16 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         self
     }
 
+    pub fn with_closure(mut self, closure: Closure) -> FunctionTranslator<'a, M> {
+        self.closure = Some(closure);
+        self
+    }
+
+    pub fn with_closures(mut self, closures: HashMap<String, usize>) -> FunctionTranslator<'a, M> {
+        self.closures = closures;
+        self
+    }
+
     pub fn with_body(self, nodes: Vec<Node>) -> Result<TranslatedFunction<'a, M>, Error> {
         self.with_body_generator(|translator, builder| {
             translator.translate_nodes(nodes, builder)?;
@@ -116,7 +193,12 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         let mut build_ctx = FunctionBuilderContext::new();
 
         let mut context = Context::new();
-        let sig = self.codegen.build_cranelift_signature(&self.signature)?;
+        let mut sig = self.codegen.build_cranelift_signature(&self.signature)?;
+
+        if self.closure.is_some() {
+            sig.params.push(AbiParam::new(pointer_type()));
+        }
+
         context.func.signature = sig;
 
         // TODO This is computed even when not needed
@@ -131,7 +213,21 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         builder.append_block_params_for_function_params(entry);
         builder.switch_to_block(entry);
 
-        let vals = builder.block_params(entry);
+        let mut vals = builder.block_params(entry).to_vec();
+
+        if let Some(closure) = &self.closure {
+            let record = vals.pop().unwrap();
+
+            for (i, (name, typ)) in closure.captures.iter().enumerate() {
+                let value = builder.ins().load(*typ, MemFlags::trusted(), record, ((i + 1) * 8) as i32);
+                self.variables.insert(name.clone(), value);
+            }
+
+            if let Some(name) = &closure.name {
+                self.variables.insert(name.clone(), record);
+            }
+        }
+
         self.stack.extend(vals);
 
         builder.seal_block(entry);
@@ -151,9 +247,47 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
     }
 
     pub fn translate_nodes(&mut self, mut nodes: Vec<Node>, builder: &mut FunctionBuilder) -> Result<(), Error> {
-        let transforms = self.codegen.transformations.clone();
+        // The other named functions were moved into the code graph
+        let closures: HashSet<String> = nodes
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Node::Literal { value: Literal::Function(..), .. }, Node::Assigment { name, .. }] => Some(name.clone()),
+
+                _ => None
+            })
+            .collect();
 
         'outer: while !nodes.is_empty() {
+            if let [Node::Literal { value: Literal::Function(sig, body), .. }, Node::Assigment { name, .. }, ..] = &nodes[..] {
+                // The record of a closure contains the values it captures, so they have to exist
+                let pending = free_names(body)
+                    .into_iter()
+                    .find(|var| var != name && closures.contains(var) && !self.variables.contains_key(var));
+
+                if let Some(var) = pending {
+                    return Err(error(format!("The closure {var} is used by {name} before it is defined, which cannot be compiled yet")))
+                }
+
+                let closure = self.build_closure(sig.clone(), body.clone(), Some(name.clone()), None, builder)?;
+                self.push_value(closure);
+
+                nodes.remove(0);
+                continue;
+            }
+
+            // The calls of named functions are renamed to their ids, which may be the names of words too
+            if let [Node::Call { name, .. }, ..] = &nodes[..] {
+                if self.codegen.code_graph.funcs.contains_key(name) {
+                    let top = nodes.remove(0);
+                    self.translate_single_node(top, builder)?;
+
+                    continue;
+                }
+            }
+
+            // Declarations may add new transformations while translating
+            let transforms = self.codegen.transformations.clone();
+
             for transform in transforms.iter() {
                 // Try to apply one of the known transforms
                 if transform.try_apply(&mut nodes, self, builder)? {
@@ -169,6 +303,42 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         Ok(())
     }
 
+    /// The named function compiled for the types it is used with. It is declared
+    /// before its body is translated, so that it can call itself
+    fn function_instance(&mut self, id: &str, sig: &TypedSignature) -> Result<(FuncId, TypedSignature), Error> {
+        let info = &self.codegen.code_graph.funcs[id];
+        let (vars, instance) = instance_signature(&info.sig, sig);
+
+        let name = mangle(id, &instance);
+
+        if info.instances.contains(&instance) {
+            return Ok((self.codegen.get_func_by_name(&name)?, instance))
+        }
+
+        let body = info.nodes
+            .iter()
+            .map(|node| node.substitute(&vars))
+            .collect();
+
+        let mut cranelift_sig = self.codegen.build_cranelift_signature(&instance)?;
+        cranelift_sig.call_conv = FunctionOptions::internal().call_conv;
+
+        let func = self.codegen.module.declare_function(&name, Linkage::Local, &cranelift_sig)?;
+
+        self.codegen.code_graph.funcs
+            .get_mut(id)
+            .unwrap()
+            .instances
+            .insert(instance.clone());
+
+        FunctionTranslator::new(self.codegen)
+            .with_signature(instance.clone())
+            .with_body(body)?
+            .finish_declared_func(func, FunctionOptions::internal())?;
+
+        Ok((func, instance))
+    }
+
     fn translate_single_node(&mut self, node: Node, builder: &mut FunctionBuilder) -> Result<(), Error> {
         match node {
             Node::Assigment { name, .. } => {
@@ -176,63 +346,244 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
                 self.variables.insert(name, node);
             },
     
-            Node::Variable { name, .. } => {
-                let val = self.variables.get(&name)
-                    .cloned()
-                    .or_else(|| self.codegen.get_func_by_name(&name)
-                        .ok()
-                        .map(|func_id| {
-                            let callee = self.codegen
-                                .module
-                                .declare_func_in_func(func_id, builder.func);
-
-                            builder.ins().func_addr(pointer_type(), callee)
-                        })
-                    )
-                    .ok_or_else(|| error(format!("Variable {name} not found - yes this is a compiler bug")))?;
+            Node::Variable { name, typ, .. } => {
+                let val = match self.variables.get(&name) {
+                    // A generic closure gets a record with the code of the instance
+                    Some(record) if self.closures.contains_key(&name) => {
+                        let record = *record;
+                        let template = self.closures[&name];
+
+                        let (args, rets) = typ
+                            .extract_function()
+                            .ok_or_else(|| error(format!("Closure {name} is not a function - yes this is a compiler bug")))?;
+
+                        self.closure_value(template, record, &TypedSignature::new(args, rets), builder)?
+                    },
+
+                    Some(val) => *val,
+
+                    // A plain function is pushed as a closure without captures
+                    None => {
+                        let (args, rets) = typ
+                            .extract_function()
+                            .ok_or_else(|| error(format!("Variable {name} not found - yes this is a compiler bug")))?;
+
+                        let sig = TypedSignature::new(args, rets);
+
+                        let (id, sig) = if self.codegen.code_graph.funcs.contains_key(&name) {
+                            self.function_instance(&name, &sig)?
+                        }
+                        else {
+                            (self.codegen.get_func_by_name(&name)?, sig)
+                        };
+
+                        self.function_value(id, sig, builder)?
+                    }
+                };
 
                 self.push_value(val);
             },
     
-            Node::Call { name, arguments, .. } => 
-                self.ins_call(name, arguments.len(), builder)?,
+            // Closures assigned to a name are called with their record
+            Node::Call { name, arguments, returns, .. } => match self.variables.get(&name) {
+                Some(record) if self.closures.contains_key(&name) => {
+                    let record = *record;
+                    let func = self.closure_instance(self.closures[&name], &TypedSignature::new(arguments.clone(), returns))?;
+
+                    self.push_value(record);
+                    self.ins_call_func(func, arguments.len() + 1, builder)
+                },
+
+                Some(closure) => {
+                    self.push_value(*closure);
+                    self.ins_call_indirect(&types::func_type(arguments, returns), builder)?
+                },
+
+                None if self.codegen.code_graph.funcs.contains_key(&name) => {
+                    let (func, _) = self.function_instance(&name, &TypedSignature::new(arguments.clone(), returns))?;
+                    self.ins_call_func(func, arguments.len(), builder)
+                },
+
+                None => self.ins_call(name, arguments.len(), builder)?
+            },
     
             Node::Literal { typ, value, .. } => {
                 let val = self.build_literal(typ, value, builder)?;
                 self.stack.push(val);
+            },
+
+            Node::Record { record, .. } =>
+                self.codegen.declare_record(record),
+
+            Node::Union { union, .. } =>
+                self.codegen.declare_union(union),
+
+            // Aliases are gone after parsing
+            Node::TypeAlias { .. } => (),
+
+            // Classes only exist for the typechecker, their methods are translated per instance
+            Node::Class { .. } => (),
+
+            Node::Instance { instance, .. } =>
+                self.codegen.declare_instance(instance),
+
+            Node::Match { union, arms, arguments, .. } =>
+                self.translate_match(union, arms, arguments, builder)?,
+
+            Node::Try { returns, .. } => 
+                self.translate_try(returns, builder),
+        }
+
+        Ok(())
+    }
+
+    fn translate_try(&mut self, returns: TypeList, builder: &mut FunctionBuilder) {
+        let flags = MemFlags::trusted();
+
+        let value = self.pop_value();
+        let tag = builder.ins().load(cranelift::prelude::types::I64, flags, value, 0);
+
+        let success_block = builder.create_block();
+        let failure_block = builder.create_block();
+
+        // Both some and ok are the first variant, so every other tag is a failure
+        builder.ins().brif(tag, failure_block, &[], success_block, &[]);
+
+        // The function returns the same kind of failure, which has the same layout
+        builder.switch_to_block(failure_block);
+        builder.ins().return_(&[value]);
+
+        builder.switch_to_block(success_block);
+        let payload = builder.ins().load(returns[0].clone().into(), flags, value, 8);
+        self.push_value(payload);
+    }
+
+    fn translate_match(
+        &mut self, 
+        union: Union, 
+        arms: Vec<Vec<Node>>, 
+        arguments: TypeList, 
+        builder: &mut FunctionBuilder
+    ) -> Result<(), Error> {
+        let value = self.pop_value();
+        let tag = builder.ins().load(cranelift::prelude::types::I64, MemFlags::trusted(), value, 0);
+
+        let mut switch = Switch::new();
+        let arm_blocks: Vec<Block> = arms
+            .iter()
+            .enumerate()
+            .map(|(tag, _)| {
+                let block = builder.create_block();
+                switch.set_entry(tag as u128, block);
+                block
+            })
+            .collect();
+
+        // The tag is always valid
+        let invalid_block = builder.create_block();
+        switch.emit(builder, tag, invalid_block);
+
+        builder.switch_to_block(invalid_block);
+        builder.ins().trap(TrapCode::UnreachableCodeReached);
+
+        let type_args = type_arguments(&union.params, arguments.last().unwrap());
+        let branches = arm_blocks.into_iter().zip(arms).collect();
+
+        self.translate_branches(branches, arguments.len() - 1, builder, |translator, tag, arm, builder| {
+            // The first value of the payload is on top, right after the tag
+            for (i, typ) in union.payload(tag).iter().enumerate().rev() {
+                let typ = typ.substitute(&type_args).into();
+                let field = builder.ins().load(typ, MemFlags::trusted(), value, (i as i32 + 1) * 8);
+                translator.push_value(field);
             }
+
+            translator.translate_nodes(arm, builder)
+        })
+    }
+
+    /// Translates every branch in its block, each one starting with the same stack. They
+    /// consume the same values and push results of the same types, which are passed to 
+    /// the block after them. `translate` is called in the block of every branch with its index
+    fn translate_branches<B, F>(
+        &mut self, 
+        branches: Vec<(Block, B)>, 
+        consumed: usize, 
+        builder: &mut FunctionBuilder,
+        translate: F
+    ) -> Result<(), Error> 
+        where F: Fn(&mut Self, usize, B, &mut FunctionBuilder) -> Result<(), Error>
+    {
+        let base = self.stack.len() - consumed;
+        let merge_block = builder.create_block();
+
+        let stack = self.stack.clone();
+        let variables = self.variables.clone();
+
+        for (i, (block, branch)) in branches.into_iter().enumerate() {
+            builder.switch_to_block(block);
+
+            self.stack = stack.clone();
+            self.variables = variables.clone();
+
+            translate(self, i, branch, builder)?;
+
+            if i == 0 {
+                for value in &self.stack[base..] {
+                    let typ = builder.func.dfg.value_type(*value);
+                    builder.append_block_param(merge_block, typ);
+                }
+            }
+
+            builder.ins().jump(merge_block, &self.stack[base..]);
         }
 
+        builder.switch_to_block(merge_block);
+
+        self.stack = stack[..base].to_vec();
+        self.stack.extend(builder.block_params(merge_block));
+        self.variables = variables;
+
         Ok(())
     }
 
     fn build_literal(&mut self, typ: Type, literal: Literal, builder: &mut FunctionBuilder) -> Result<Value, Error> {
-        if let Type::Kind(typ_name, _type_vars) = typ {
+        if let Type::Kind(typ_name, _type_vars) = &typ {
             match (typ_name.as_str(), literal) {
-                (types::QUOTE_TYPE_NAME, Literal::Quote(value)) => {
-                    let content = value.as_bytes().to_vec();
-                    let len_buf = content.len().to_le_bytes();
+                (types::QUOTE_TYPE_NAME, Literal::Quote(value)) => 
+                    self.build_quote(&value, builder),
 
-                    let mut buffer: Vec<u8> = Vec::new();
-                    buffer.extend(len_buf);  // Save str len
-                    buffer.extend(content);  // Save content
-                    buffer.push(0);          // Save 0 byte
+                (types::QUOTE_TYPE_NAME, Literal::Interpolation(parts)) => {
+                    let mut strs = Vec::new();
 
-                    let id = self.codegen.create_data(buffer)?;
+                    for part in parts {
+                        let str = match part {
+                            InterpolationPart::Text(text) => self.build_quote(&text, builder)?,
 
-                    let local_id = self
-                        .codegen
-                        .module
-                        .declare_data_in_func(id, builder.func);
+                            // The parser made sure the code results in a single str
+                            InterpolationPart::Code(ast) => {
+                                self.translate_nodes(ast, builder)?;
+                                self.pop_value()
+                            },
+                        };
+
+                        strs.push(str);
+                    }
 
-                    let pointer = pointer_type();
-                    Ok(builder.ins().symbol_value(pointer, local_id))
+                    self.concat_strings(strs, builder)
                 },
     
                 (types::NUMBER_TYPE_NAME, Literal::Number(value)) => {
                     Ok(builder.ins().f64const(value))
                 },
 
+                (types::INTEGER_TYPE_NAME, Literal::Integer(value)) => {
+                    Ok(builder.ins().iconst(cranelift::prelude::types::I64, value))
+                },
+
+                (types::BOOL_TYPE_NAME, Literal::Bool(value)) => {
+                    Ok(builder.ins().iconst(cranelift::prelude::types::I8, value as i64))
+                },
+
                 (types::LIST_TYPE_NAME, Literal::List(ast)) => {
                     let stack_size_before = self.stack.len();
 
@@ -245,7 +596,8 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
                         .collect();
 
                     // Allocate the list
-                    let data = self.codegen.create_data(vec![0;vals.len() * 8])?;
+                    // The length is stored in front of the elements
+                    let data = self.codegen.create_data(vec![0; (vals.len() + 1) * 8])?;
                     let local_id = self.codegen
                         .module
                         .declare_data_in_func(data, builder.func);
@@ -267,18 +619,33 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
                     Ok(address)
                 },
     
-                (types::FUNC_TYPE_NAME, Literal::Function(sig, ast)) => {
-                    let (id, _) = FunctionTranslator::new(self.codegen)
-                        .with_signature(sig)
-                        .with_body(ast)?
-                        .finish_anon_func(FunctionOptions::internal())?;
+                (types::TUPLE_TYPE_NAME, Literal::Tuple(ast)) => {
+                    let stack_size_before = self.stack.len();
 
-                    let local_callee = self
-                        .codegen
-                        .module
-                        .declare_func_in_func(id, builder.func);
+                    self.translate_nodes(ast, builder)?;
 
-                    Ok(builder.ins().func_addr(pointer_type(), local_callee))
+                    // The first element is the one on top
+                    let vals: Vec<Value> = self.stack.drain(stack_size_before..)
+                        .rev()
+                        .collect();
+
+                    // Tuples are allocated like records, as they may be created in a loop
+                    let size = builder.ins().iconst(cranelift::prelude::types::I64, (vals.len() * 8) as i64);
+                    self.push_value(size);
+                    self.ins_call("malloc", 1, builder)?;
+                    let address = self.pop_value();
+
+                    for (i, val) in vals.iter().enumerate() {
+                        builder.ins().store(MemFlags::trusted(), *val, address, (i * 8) as i32);
+                    }
+
+                    Ok(address)
+                },
+    
+                (types::FUNC_TYPE_NAME, Literal::Function(sig, ast)) => {
+                    let (args, rets) = typ.extract_function().unwrap();
+
+                    self.build_closure(sig, ast, None, Some(&TypedSignature::new(args, rets)), builder)
                 },
     
                 _ => unreachable!()
@@ -288,9 +655,247 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
         else { unreachable!() }
     }
 
+    /// Creates the record of the function with the current values of the variables it captures,
+    /// the ones which are not variables are plain functions. The function is compiled for the
+    /// signature it is used with, a named one when it is not generic or else for every call
+    fn build_closure(
+        &mut self, 
+        sig: TypedSignature, 
+        body: Vec<Node>, 
+        name: Option<String>, 
+        used: Option<&TypedSignature>, 
+        builder: &mut FunctionBuilder
+    ) -> Result<Value, Error> {
+        let captured: Vec<(String, Value)> = free_names(&body)
+            .into_iter()
+            .filter(|var| Some(var) != name.as_ref())
+            .filter_map(|var| self.variables.get(&var).map(|val| (var, *val)))
+            .collect();
+
+        let captures = captured
+            .iter()
+            .map(|(var, val)| (var.clone(), builder.func.dfg.value_type(*val)))
+            .collect();
+
+        let template = self.codegen.closure_templates.len();
+
+        let mut closures: HashMap<String, usize> = captured
+            .iter()
+            .filter_map(|(var, _)| self.closures.get(var).map(|template| (var.clone(), *template)))
+            .collect();
+
+        if let Some(name) = &name {
+            closures.insert(name.clone(), template);
+            self.closures.insert(name.clone(), template);
+        }
+
+        self.codegen.closure_templates.push(ClosureTemplate { 
+            sig: sig.clone(), 
+            body, 
+            closure: Closure { name, captures }, 
+            closures, 
+            instances: HashMap::new() 
+        });
+
+        // A generic record is only called by its name, which uses the instance for the types
+        let code = match used {
+            Some(used) => Some(self.closure_instance(template, used)?),
+
+            None if !self.codegen.closure_templates[template].is_generic() => Some(self.closure_instance(template, &sig)?),
+
+            None => None
+        };
+
+        let code = match code {
+            Some(id) => self.function_address(id, builder),
+
+            None => builder.ins().iconst(pointer_type(), 0)
+        };
+
+        let values = captured
+            .into_iter()
+            .map(|(_, val)| val)
+            .collect();
+
+        self.build_closure_record(code, values, builder)
+    }
+
+    /// The closure compiled for the signature it is used with, it takes the record
+    /// as its last argument. It is declared before its body is translated, so that
+    /// it can call itself
+    fn closure_instance(&mut self, template: usize, sig: &TypedSignature) -> Result<FuncId, Error> {
+        let closure = &self.codegen.closure_templates[template];
+        let (vars, instance) = instance_signature(&closure.sig, sig);
+
+        if let Some(id) = closure.instances.get(&instance) {
+            return Ok(*id)
+        }
+
+        let body = closure.body
+            .iter()
+            .map(|node| node.substitute(&vars))
+            .collect();
+
+        let closures = closure.closures.clone();
+        let closure = closure.closure.clone();
+
+        let mut cranelift_sig = self.codegen.build_cranelift_signature(&instance)?;
+        cranelift_sig.call_conv = FunctionOptions::internal().call_conv;
+        cranelift_sig.params.push(AbiParam::new(pointer_type()));
+
+        let func = self.codegen.module.declare_anonymous_function(&cranelift_sig)?;
+
+        self.codegen.closure_templates[template].instances.insert(instance.clone(), func);
+
+        FunctionTranslator::new(self.codegen)
+            .with_signature(instance)
+            .with_closure(closure)
+            .with_closures(closures)
+            .with_body(body)?
+            .finish_declared_func(func, FunctionOptions::internal())?;
+
+        Ok(func)
+    }
+
+    /// The closure as a value of the given type, the record of a generic one is 
+    /// copied with the code of the instance
+    fn closure_value(&mut self, template: usize, record: Value, sig: &TypedSignature, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        if !self.codegen.closure_templates[template].is_generic() {
+            return Ok(record)
+        }
+
+        let func = self.closure_instance(template, sig)?;
+        let code = self.function_address(func, builder);
+
+        let values = self.codegen.closure_templates[template].closure.captures
+            .iter()
+            .enumerate()
+            .map(|(i, (_, typ))| builder.ins().load(*typ, MemFlags::trusted(), record, ((i + 1) * 8) as i32))
+            .collect();
+
+        self.build_closure_record(code, values, builder)
+    }
+
+    /// A plain function as a value, its code is an adapter which takes the record
+    fn function_value(&mut self, id: FuncId, sig: TypedSignature, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        let adapter = match self.codegen.closure_adapters.get(&id) {
+            Some(adapter) => *adapter,
+
+            None => {
+                let (adapter, _) = FunctionTranslator::new(self.codegen)
+                    .with_signature(sig)
+                    .with_closure(Closure::default())
+                    .with_body_generator(|trans, builder| {
+                        let args_len = trans.stack.len();
+                        trans.ins_call_func(id, args_len, builder);
+
+                        Ok(())
+                    })?
+                    .finish_anon_func(FunctionOptions::internal())?;
+
+                self.codegen.closure_adapters.insert(id, adapter);
+                adapter
+            }
+        };
+
+        let code = self.function_address(adapter, builder);
+        self.build_closure_record(code, Vec::new(), builder)
+    }
+
+    fn build_closure_record(&mut self, code: Value, captures: Vec<Value>, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        // Records are allocated, as closures may be created in a loop
+        let size = builder.ins().iconst(cranelift::prelude::types::I64, ((captures.len() + 1) * 8) as i64);
+        self.push_value(size);
+        self.ins_call("malloc", 1, builder)?;
+        let address = self.pop_value();
+
+        builder.ins().store(MemFlags::trusted(), code, address, 0);
+
+        for (i, val) in captures.iter().enumerate() {
+            builder.ins().store(MemFlags::trusted(), *val, address, ((i + 1) * 8) as i32);
+        }
+
+        Ok(address)
+    }
+
+    pub fn function_address(&mut self, id: FuncId, builder: &mut FunctionBuilder) -> Value {
+        let callee = self.codegen
+            .module
+            .declare_func_in_func(id, builder.func);
+
+        builder.ins().func_addr(pointer_type(), callee)
+    }
+
+    pub fn build_data(&mut self, content: Vec<u8>, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        let id = self.codegen.create_data(content)?;
+
+        let local_id = self
+            .codegen
+            .module
+            .declare_data_in_func(id, builder.func);
+
+        Ok(builder.ins().symbol_value(pointer_type(), local_id))
+    }
+
+    pub fn build_quote(&mut self, value: &str, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        let content = value.as_bytes().to_vec();
+        let len_buf = content.len().to_le_bytes();
+
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.extend(len_buf);  // Save str len
+        buffer.extend(content);  // Save content
+        buffer.push(0);          // Save 0 byte
+
+        self.build_data(buffer, builder)
+    }
+
+    /// Concatenates the given strs into a newly allocated one
+    pub fn concat_strings(&mut self, strs: Vec<Value>, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        let flags = MemFlags::trusted();
+        let len_type = cranelift::prelude::types::I64;
+
+        let lens: Vec<Value> = strs
+            .iter()
+            .map(|str| builder.ins().load(len_type, flags, *str, 0))
+            .collect();
+
+        let mut total_len = builder.ins().iconst(len_type, 0);
+        for len in lens.iter() {
+            total_len = builder.ins().iadd(total_len, *len);
+        }
+
+        // Length + content + 0 byte
+        let size = builder.ins().iadd_imm(total_len, 9);
+        self.push_value(size);
+        self.ins_call("malloc", 1, builder)?;
+        let result = self.pop_value();
+
+        builder.ins().store(flags, total_len, result, 0);
+
+        let config = self.codegen.module.target_config();
+        let mut dest = builder.ins().iadd_imm(result, 8);
+
+        for (str, len) in strs.into_iter().zip(lens) {
+            let src = builder.ins().iadd_imm(str, 8);
+            builder.call_memcpy(config, dest, src, len);
+
+            dest = builder.ins().iadd(dest, len);
+        }
+
+        let zero = builder.ins().iconst(cranelift::prelude::types::I8, 0);
+        builder.ins().store(flags, zero, dest, 0);
+
+        Ok(result)
+    }
+
     pub fn ins_call<S: AsRef<str>>(&mut self, name: S, args_len: usize, builder: &mut FunctionBuilder) -> Result<(), Error> {
         let func_id = self.codegen.get_func_by_name(name.as_ref())?;
-        
+        self.ins_call_func(func_id, args_len, builder);
+
+        Ok(())
+    }
+
+    pub fn ins_call_func(&mut self, func_id: FuncId, args_len: usize, builder: &mut FunctionBuilder) {
         let local_callee = self
             .codegen
             .module
@@ -303,6 +908,187 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
 
         let results = builder.inst_results(call);
         self.stack.extend_from_slice(results);
+    }
+
+    /// Calls the closure on top with the values below it and its record, the
+    /// signature is built from the `arg` and `ret` lists of its type
+    pub fn ins_call_indirect(&mut self, fun: &Type, builder: &mut FunctionBuilder) -> Result<(), Error> {
+        let (args, rets) = function_effect(fun)?;
+
+        let args_len = args.len();
+
+        let mut cranelift_sig = self.codegen.build_cranelift_signature(&TypedSignature::new(args, rets))?;
+        cranelift_sig.call_conv = FunctionOptions::internal().call_conv;
+        cranelift_sig.params.push(AbiParam::new(pointer_type()));
+
+        let sig_ref = builder.import_signature(cranelift_sig);
+
+        let record = self.pop_value();
+        let callee = builder.ins().load(pointer_type(), MemFlags::trusted(), record, 0);
+
+        let range = (self.stack.len() - args_len)..;
+
+        let mut slice: Vec<Value> = self.stack.drain(range).collect();
+        slice.push(record);
+
+        let call = builder.ins().call_indirect(sig_ref, callee, &slice[..]);
+
+        let results = builder.inst_results(call);
+        self.stack.extend_from_slice(results);
+
+        Ok(())
+    }
+
+    /// Calls the closure `then` with its type if the condition is true, `otherwise` if not. 
+    /// Without `otherwise`, the stack stays as it is. A closure which takes fewer values than
+    /// the other one leaves the ones below as they are
+    pub fn ins_branch(
+        &mut self, 
+        condition: Value, 
+        then: (Value, &Type), 
+        otherwise: Option<(Value, &Type)>, 
+        builder: &mut FunctionBuilder
+    ) -> Result<(), Error> {
+        let then_block = builder.create_block();
+        let otherwise_block = builder.create_block();
+
+        builder.ins().brif(condition, then_block, &[], otherwise_block, &[]);
+
+        let mut consumed = 0;
+        for (_, typ) in [Some(then), otherwise].iter().flatten() {
+            consumed = consumed.max(function_effect(typ)?.0.len());
+        }
+
+        let branches = vec![(then_block, Some(then)), (otherwise_block, otherwise)];
+
+        self.translate_branches(branches, consumed, builder, |translator, _, closure, builder| {
+            match closure {
+                Some((closure, typ)) => {
+                    translator.push_value(closure);
+                    translator.ins_call_indirect(typ, builder)
+                },
+
+                None => Ok(())
+            }
+        })
+    }
+
+    /// Calls the closure `body` with its type until the control says otherwise. The header block 
+    /// checks whether the body runs again, its parameters are the index of the run (except for 
+    /// while loops) and the state of the loop: the values the closures take and leave again. 
+    /// Both the body and the block after the loop continue with the state
+    pub fn ins_loop(&mut self, control: LoopControl, body: (Value, &Type), builder: &mut FunctionBuilder) -> Result<(), Error> {
+        let int = cranelift::prelude::types::I64;
+        let (body, body_type) = body;
+        let body_len = function_effect(body_type)?.0.len();
+
+        let state_len = match &control {
+            LoopControl::Times(_) => body_len,
+
+            // The element is not part of the state
+            LoopControl::Each(..) => body_len.saturating_sub(1),
+
+            LoopControl::While(_, condition) => body_len.max(function_effect(condition)?.0.len())
+        };
+
+        let base = self.stack.len() - state_len;
+        let state_types: Vec<_> = self.stack[base..]
+            .iter()
+            .map(|value| builder.func.dfg.value_type(*value))
+            .collect();
+
+        let state_block = |builder: &mut FunctionBuilder| {
+            let block = builder.create_block();
+
+            for typ in &state_types {
+                builder.append_block_param(block, *typ);
+            }
+
+            block
+        };
+
+        let header_block = state_block(builder);
+        let body_block = state_block(builder);
+        let exit_block = state_block(builder);
+
+        let counted = !matches!(control, LoopControl::While(..));
+        if counted {
+            builder.append_block_param(header_block, int);
+            builder.append_block_param(body_block, int);
+        }
+
+        let mut initial = self.stack[base..].to_vec();
+        if counted {
+            initial.push(builder.ins().iconst(int, 0));
+        }
+
+        builder.ins().jump(header_block, &initial);
+
+        let stack = self.stack[..base].to_vec();
+
+        // Header
+        builder.switch_to_block(header_block);
+
+        let mut params = builder.block_params(header_block).to_vec();
+        let index = counted.then(|| params.pop().unwrap());
+
+        self.stack = stack.clone();
+        self.stack.extend(params);
+
+        let run_body = match (&control, index) {
+            (LoopControl::Times(count), Some(index)) => 
+                builder.ins().icmp(IntCC::SignedLessThan, index, *count),
+
+            (LoopControl::Each(list, _), Some(index)) => {
+                let len = builder.ins().load(int, MemFlags::trusted(), *list, 0);
+                builder.ins().icmp(IntCC::SignedLessThan, index, len)
+            },
+
+            (LoopControl::While(condition, typ), _) => {
+                self.push_value(*condition);
+                self.ins_call_indirect(typ, builder)?;
+                self.pop_value()
+            },
+
+            _ => unreachable!()
+        };
+
+        let mut state = self.stack[base..].to_vec();
+        let exit_args = state.clone();
+        state.extend(index);
+
+        builder.ins().brif(run_body, body_block, &state, exit_block, &exit_args);
+
+        // Body
+        builder.switch_to_block(body_block);
+
+        let mut params = builder.block_params(body_block).to_vec();
+        let index = counted.then(|| params.pop().unwrap());
+
+        self.stack = stack.clone();
+        self.stack.extend(params);
+
+        // The elements are stored after the length of the list
+        if let (LoopControl::Each(list, Type::Kind(_, inner)), Some(index)) = (&control, index) {
+            let offset = builder.ins().imul_imm(index, 8);
+            let address = builder.ins().iadd(*list, offset);
+            let element = builder.ins().load(inner[0].clone().into(), MemFlags::trusted(), address, 8);
+            self.push_value(element);
+        }
+
+        self.push_value(body);
+        self.ins_call_indirect(body_type, builder)?;
+
+        let mut next = self.stack[base..].to_vec();
+        next.extend(index.map(|index| builder.ins().iadd_imm(index, 1)));
+
+        builder.ins().jump(header_block, &next);
+
+        // After the loop
+        builder.switch_to_block(exit_block);
+
+        self.stack = stack;
+        self.stack.extend(builder.block_params(exit_block));
 
         Ok(())
     }
@@ -314,4 +1100,26 @@ impl<'a, M: Module> FunctionTranslator<'a, M> {
     pub fn push_value(&mut self, val: Value) {
         self.stack.push(val)
     }
-}
\ No newline at end of file
+}
+
+/// The arguments and returns of a function value
+fn function_effect(fun: &Type) -> Result<(TypeList, TypeList), Error> {
+    fun
+        .extract_function()
+        .ok_or_else(|| error(format!("Cannot call a value of type {fun}")))
+}
+
+/// The substitution of the variables of a generic signature for the one it is used with,
+/// together with the signature of the instance. Only the values above the row are passed,
+/// whatever it stands for
+fn instance_signature(generic: &TypedSignature, used: &TypedSignature) -> (Substitution, TypedSignature) {
+    let vars = generic.match_vars(used);
+    let substituted = generic.substitute(&vars);
+
+    let instance = TypedSignature::new(
+        substituted.arguments().vec().clone().into(), 
+        substituted.returns().vec().clone().into()
+    );
+
+    (vars, instance)
+}
@@ -3,7 +3,7 @@ use std::{fs, mem};
 use cranelift_jit::{JITModule, JITBuilder};
 use cranelift_module::Module;
 
-use crate::{parser::{types::{type_env::TypeEnv, typelist::TypeList}, parse, node::Node}, error::{Error, error}, lexer::{lex, token::Token}, debug_printer::*, config::{DebugConfig, FileRunningConfig}, stdlib::create_stdlib};
+use crate::{parser::{types::type_env::TypeEnv, parse_program, node::Node}, error::{Error, error}, lexer::lex, source::{SourceId, add_source}, debug_printer::*, config::{DebugConfig, FileRunningConfig}, stdlib::create_stdlib};
 
 use super::{codegen_module::CodeGenModule, fail, function_translator::FunctionOptions, jit_ffi::{RawJitState, JitState}, native_isa};
 
@@ -38,22 +38,22 @@ impl Jit {
     pub fn run_file(&mut self, config: &FileRunningConfig, debug_config: &DebugConfig){
         let input_file = config.file.clone();
 
-        match fs::read_to_string(input_file) {
-            Ok(src) => self.run_file_content(src, debug_config),
+        match fs::read_to_string(&input_file) {
+            Ok(src) => self.run_file_content(add_source(input_file.display(), src), debug_config),
 
-            Err(err) => fail(error(err), "".to_string()),
+            Err(err) => fail(error(err)),
         }
     }
 
-    fn run_file_content(&mut self, expr: String, debug_config: &DebugConfig){
-        if let Err(errs) = self.run(expr.clone(), debug_config) {
-            fail(errs, expr)
+    fn run_file_content(&mut self, source: SourceId, debug_config: &DebugConfig){
+        if let Err(errs) = self.run(source, debug_config) {
+            fail(errs)
         }
     }
 
-    pub fn run_saving(&mut self, expr: String, debug_config: &DebugConfig) -> Result<(), Error> {
+    pub fn run_saving(&mut self, source: SourceId, debug_config: &DebugConfig) -> Result<(), Error> {
         // Parsing
-        let mut ast = self.lex_and_parse(expr, debug_config)?;
+        let mut ast = self.lex_and_parse(source, debug_config)?;
 
         // Insert save call for saving stack state
         ast.push(Node::new_marker_call("__save"));
@@ -75,16 +75,16 @@ impl Jit {
 
         // Running
         unsafe {
-            let fun = mem::transmute::<_, fn(*mut RawJitState) -> ()>(pointer);
+            let fun = mem::transmute::<*const u8, fn(*mut RawJitState)>(pointer);
             fun(&mut self.state);
         }
         
         Ok(())
     }
 
-    pub fn run(&mut self, expr: String, debug_config: &DebugConfig) -> Result<(), Error> {
+    pub fn run(&mut self, source: SourceId, debug_config: &DebugConfig) -> Result<(), Error> {
         // Parsing
-        let ast = self.lex_and_parse(expr, debug_config)?;
+        let ast = self.lex_and_parse(source, debug_config)?;
 
         // Translating
         let isa = self.codegen.module.target_config();
@@ -103,20 +103,24 @@ impl Jit {
 
         // Running
         unsafe {
-            let fun = mem::transmute::<_, fn() -> ()>(pointer);
+            let fun = mem::transmute::<*const u8, fn()>(pointer);
             fun()
         }
         
         Ok(())
     }
 
-    fn lex_and_parse(&mut self, expr: String, debug_config: &DebugConfig) -> Result<Vec<Node>, Error> {
+    fn lex_and_parse(&mut self, source: SourceId, debug_config: &DebugConfig) -> Result<Vec<Node>, Error> {
         // Lexing
-        let tokens = lex(expr)?;
+        let tokens = lex(source)?;
         debug_tokens(&tokens, debug_config);
 
         // Parsing
-        let ast = parse(tokens, &mut self.type_env)?;
+        // Constraints and types left over from a line with an error are gone
+        self.type_env.wanted = Default::default();
+        self.type_env.substitution = Default::default();
+
+        let ast = parse_program(tokens, &mut self.type_env)?;
         debug_ast(&ast, debug_config);
 
         Ok(ast)
@@ -132,3 +136,84 @@ impl Jit {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{source::add_source, config::DebugConfig};
+
+    use super::Jit;
+
+    /// The stack after running the program, as shown by the REPL
+    fn run(src: &str) -> String {
+        let mut jit = Jit::new();
+
+        jit.run_saving(add_source("test", src.to_string()), &DebugConfig::default())
+            .unwrap_or_else(|_| panic!("Could not run {src}"));
+
+        jit.jit_state().to_string()
+    }
+
+    #[test]
+    fn generic_closures() {
+        assert_eq!(run("n: 1\nf: ( 'a -- 'a num ) { n }\nf \"x\"\nf 2i"), "\"x\" 1 2 1");
+        assert_eq!(run("n: 1\nf: ( 'a -- 'a num ) { n }\ncall :f true"), "true 1");
+
+        // Calls of itself use the instance for their own types
+        assert_eq!(run("k: 3i\nf: ( 'a int -- 'a ) { ifelse { f iadd -1i } { drop } igt k dup }\nf 5i \"x\""), "\"x\"");
+    }
+
+    #[test]
+    fn generic_function_values() {
+        assert_eq!(run("sq: { mul dup }\ncall :sq 3\ncall :sq 4i"), "9 16");
+        assert_eq!(run("id: ( 'a -- 'a ) { }\ncall :id \"x\"\ncall :id 1.5"), "\"x\" 1.5");
+
+        // Inside a generic closure the value is generic until the closure is instantiated
+        assert_eq!(run("k: 3i\nsq: { mul dup }\nf: ( 'a -- 'a int ) { k call :sq }\nf 4\nf 2i"), "16 3 4 3");
+    }
+
+    #[test]
+    fn branches_take_function_values() {
+        assert_eq!(run("ifelse { \"yes\" } { \"no\" } true\nifelse { fadd 1 } { } false 2"), "\"yes\" 2");
+        assert_eq!(run("double: { fmul 2 }\nif :double true 3\ndrop dip { if :double false } \"kept\" 1"), "6 1");
+
+        // The values the branches take are inferred as well
+        assert_eq!(run("f: { if { fadd 1 } }\nf true 2\nf false 2"), "3 2");
+    }
+
+    #[test]
+    fn loops_take_function_values() {
+        assert_eq!(run("double: { fmul 2 }\ntimes :double 3i 1\nwhile { flt 50 dup } :double 1"), "8 1");
+        assert_eq!(run("each { iadd } [ 1i 2i 3i ] 0i\ndrop dip { times { fmul 2 } 2i } \"kept\" 1"), "6 4");
+        assert_eq!(run("f: { times { fadd 1 } }\nf 3i 2"), "5");
+    }
+
+    #[test]
+    fn unbalanced_match_arms() {
+        let src = "union opt [sm[num] nn]\nmatch [sm { fadd } nn { }] sm 1 5\nmatch [sm { fadd } nn { }] nn 7";
+
+        assert_eq!(run(src), "6 7");
+    }
+
+    #[test]
+    fn number_formatting() {
+        let cases = [
+            ("1.5", "1.5"),
+            ("100", "100"),
+            ("0.1", "0.1"),
+            ("0.00012345", "0.00012345"),
+            ("0.00001", "1e-05"),
+            ("fdiv 1 3", "0.333333333333333"),
+            ("123456789012345678", "1.23456789012346e+17"),
+            ("100000000000000000000", "1e+20"),
+            ("9.9999999999999999", "10"),
+            ("fsub 0 3.25", "-3.25"),
+            ("fmul -1 0", "-0"),
+            ("fdiv 1 0", "inf"),
+            ("fdiv -1 0", "-inf")
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(run(&format!("num2str {src}")), format!("\"{expected}\""), "{src}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use core::slice;
 use std::fmt::Display;
 
-use crate::parser::types::{type_env::{TypeEnv, TypeBindings}, typ::Type, *, typelist::TypeList};
+use crate::parser::{types::{type_env::{TypeEnv, TypeBindings}, typ::Type, *, typelist::TypeList}, signature_parser::TypedSignature};
 
 // The struct is only allocated inside our Jit which should in theory align
 // this thing
@@ -21,14 +21,14 @@ impl RawJitState {
             Vec::new()
         }
         else {
-            values_from_raw(&self.stack[..tenv.stack.len()], &tenv.stack)
+            values_from_raw(&self.stack[..tenv.stack.len()], &tenv.stack, tenv)
         };
 
         let vars = if tenv.bindings.is_empty() {
             Vec::new()
         }
         else {
-            values_from_raw(&self.vars[..tenv.bindings.len()], &layout_bindings(&tenv.bindings))
+            values_from_raw(&self.vars[..tenv.bindings.len()], &layout_bindings(&tenv.bindings), tenv)
         };
 
         JitState { stack, vars }
@@ -38,14 +38,21 @@ impl RawJitState {
 #[derive(Debug)]
 pub enum JitValue {
     Number(f64),
+    Integer(i64),
+    Bool(bool),
     Quote(String),
     List(Vec<JitValue>),
+    Tuple(Vec<JitValue>),
+    Function(TypedSignature),
+    Record(String, Vec<(String, JitValue)>),
+    Variant(String, Vec<JitValue>),
     Other(String, usize)
 }
 
 #[derive(Debug)]
 pub struct JitState {
     stack: Vec<JitValue>,
+    #[allow(dead_code)] // TODO Vars are not saved yet
     vars: Vec<JitValue>
 }
 
@@ -60,26 +67,34 @@ fn layout_bindings(bindings: &TypeBindings) -> TypeList {
 
      type_bindings_sorted_keys(bindings)
         .iter()
-        .map(|key| bindings.get(key).unwrap().clone())
+        .map(|key| bindings.get(key).unwrap().typ.clone())
         .for_each(|typ| list.push(typ));
 
     list
 }
 
-unsafe fn values_from_raw(slice: &[usize], types: &TypeList) -> Vec<JitValue> {
+unsafe fn values_from_raw(slice: &[usize], types: &TypeList, tenv: &TypeEnv) -> Vec<JitValue> {
     slice.iter()
         .zip(types.vec())
-        .map(|(ptr, typ)| convert(*ptr, typ))
+        .map(|(ptr, typ)| convert(*ptr, typ, tenv))
         .collect()
 }
 
-unsafe fn convert(pointer: usize, typ: &Type) -> JitValue {
+/// The type environment knows the layout of records and unions
+unsafe fn convert(pointer: usize, typ: &Type, tenv: &TypeEnv) -> JitValue {
     match typ {
         Type::Kind(name, _) if name == NUMBER_TYPE_NAME => {
             let val = f64::from_bits(pointer.try_into().unwrap());
             JitValue::Number(val)
         },
 
+        Type::Kind(name, _) if name == INTEGER_TYPE_NAME => 
+            JitValue::Integer(pointer as i64),
+
+        // Only the lowest byte is written when saving a bool
+        Type::Kind(name, _) if name == BOOL_TYPE_NAME => 
+            JitValue::Bool(pointer & 0xFF != 0),
+
         Type::Kind(name, _) if name == QUOTE_TYPE_NAME => {
             let ptr = pointer as *const u64;
             let size: &u64 = &*ptr;
@@ -104,21 +119,75 @@ unsafe fn convert(pointer: usize, typ: &Type) -> JitValue {
             let list_ptr = ptr.offset(1);
 
             let vals: Vec<JitValue> = (0..*ptr)
-                .into_iter()
                 .map(|offset| {
                     let ptr = list_ptr.offset(offset as isize) as *const usize;
 
-                    convert(*ptr, typ)
+                    convert(*ptr, typ, tenv)
                 })
                 .collect();
 
             JitValue::List(vals)
         },
 
+        Type::Kind(name, elems) if name == TUPLE_TYPE_NAME => {
+            let ptr = pointer as *const usize;
+
+            let vals = elems
+                .iter()
+                .enumerate()
+                .map(|(offset, typ)| convert(*ptr.add(offset), typ, tenv))
+                .collect();
+
+            JitValue::Tuple(vals)
+        },
+
+        Type::Kind(name, _) if tenv.records.contains_key(name) => {
+            let record = &tenv.records[name];
+            let ptr = pointer as *const usize;
+
+            // Every field occupies 8 bytes
+            let fields = record.fields
+                .iter()
+                .enumerate()
+                .map(|(offset, (field, field_typ))| {
+                    let field_typ = field_typ.substitute(&type_arguments(&record.params, typ));
+
+                    (field.clone(), convert(*ptr.add(offset), &field_typ, tenv))
+                })
+                .collect();
+
+            JitValue::Record(name.clone(), fields)
+        },
+
+        Type::Kind(name, _) if tenv.unions.contains_key(name) => {
+            let union = &tenv.unions[name];
+            let ptr = pointer as *const usize;
+
+            // The payload follows the tag
+            let tag = *ptr;
+            let payload = union.payload(tag)
+                .iter()
+                .enumerate()
+                .map(|(offset, payload_typ)| {
+                    let payload_typ = payload_typ.substitute(&type_arguments(&union.params, typ));
+
+                    convert(*ptr.add(offset + 1), &payload_typ, tenv)
+                })
+                .collect();
+
+            JitValue::Variant(union.variants[tag].0.clone(), payload)
+        },
+
+        Type::Kind(name, _) if name == FUNC_TYPE_NAME => {
+            let (args, rets) = typ.function_effect().unwrap();
+
+            JitValue::Function(TypedSignature::new(args.clone(), rets.clone()).generalize())
+        },
+
         Type::Kind(_, _) => 
             JitValue::Other(typ.to_string(), pointer),
 
-        Type::Variable(_, _) => panic!("Variables not allowed"),
+        Type::Variable(_) => panic!("Variables not allowed"),
     }
 }
 
@@ -134,12 +203,40 @@ impl Display for JitValue {
             JitValue::Number(num) => 
                 write!(f, "{num}"),
 
+            JitValue::Integer(int) => 
+                write!(f, "{int}"),
+
+            JitValue::Bool(bool) => 
+                write!(f, "{bool}"),
+
             JitValue::Quote(str) => 
                 write!(f, "\"{str}\""),
 
             JitValue::List(vals) => 
                 write!(f, "[{}]", jit_values_to_str(vals)),
 
+            JitValue::Tuple(vals) => 
+                write!(f, "({})", jit_values_to_str(vals)),
+
+            JitValue::Function(sig) => 
+                write!(f, "{FUNC_TYPE_NAME}{sig}"),
+
+            JitValue::Record(name, fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, val)| format!("{field}: {val}"))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+
+                write!(f, "{name}[{fields}]")
+            },
+
+            JitValue::Variant(name, payload) if payload.is_empty() => 
+                write!(f, "{name}"),
+
+            JitValue::Variant(name, payload) => 
+                write!(f, "{name}[{}]", jit_values_to_str(payload)),
+
             JitValue::Other(name, addr) => 
                 write!(f, "{name}<{addr}>"),
         }
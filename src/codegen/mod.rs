@@ -4,7 +4,7 @@ use ariadne::{Color, Fmt};
 use cranelift::prelude::{AbiParam, isa::TargetIsa, settings::{*, Flags, self}};
 use cranelift_module::ModuleError;
 
-use crate::{error::Error, parser::types::{typ::Type, typelist::TypeList, NUMBER_TYPE_NAME}};
+use crate::{error::Error, parser::types::{typ::Type, typelist::TypeList, NUMBER_TYPE_NAME, INTEGER_TYPE_NAME, BOOL_TYPE_NAME}};
 
 pub mod compiler;
 pub mod jit;
@@ -13,8 +13,8 @@ pub mod external_linker;
 pub mod function_translator;
 pub mod jit_ffi;
 
-fn fail(err: Error, src: String) -> ! {
-    err.report(src);
+fn fail(err: Error) -> ! {
+    err.report();
     println!("\n\t{}", "Build failed, aborting".fg(Color::Red));
     exit(1)
 }
@@ -28,7 +28,7 @@ fn pointer_type() -> cranelift::prelude::Type {
 }
 
 fn native_isa() -> Arc<dyn TargetIsa> {
-    return match cranelift_native::builder() {
+    match cranelift_native::builder() {
         Ok(builder) => {
             // See https://github.com/bytecodealliance/wasmtime/blob/e4dc9c79443259e40f3e93b9c7815b0645ebd5c4/cranelift/jit/src/backend.rs#L50
             let mut flag_builder = settings::builder();
@@ -46,7 +46,7 @@ fn native_isa() -> Arc<dyn TargetIsa> {
         },
 
         Err(msg) => panic!("{msg}")
-    };
+    }
 }
 
 impl From<Type> for cranelift::prelude::Type {
@@ -54,6 +54,10 @@ impl From<Type> for cranelift::prelude::Type {
         match val {
             Type::Kind(name, _) if name == NUMBER_TYPE_NAME => cranelift::prelude::types::F64,
 
+            Type::Kind(name, _) if name == INTEGER_TYPE_NAME => cranelift::prelude::types::I64,
+
+            Type::Kind(name, _) if name == BOOL_TYPE_NAME => cranelift::prelude::types::I8,
+
             Type::Kind(name, _) if name == "ci32" => cranelift::prelude::types::I32,
 
             Type::Kind(name, _) if name == "ci64" => cranelift::prelude::types::I64,
@@ -62,7 +66,7 @@ impl From<Type> for cranelift::prelude::Type {
 
             Type::Kind(_, _) => pointer_type(),
 
-            Type::Variable(_, _) => panic!("Variables not allowed"),
+            Type::Variable(_) => panic!("Variables not allowed"),
         }
     }
 }
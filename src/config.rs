@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{Parser, command, Subcommand, Args};
+use clap::{Parser, Subcommand, Args};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,7 +18,7 @@ pub struct FileRunningConfig {
     pub file: PathBuf
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Default, Args)]
 pub struct DebugConfig {
     /// (Development) No code is actually executed. Useful pared with the emit options 
     #[arg(long)]
@@ -2,18 +2,24 @@ pub fn error<M: ToString>(message: M) -> Error {
     Error::General { message: message.to_string() }
 }
 
-use std::{ops::Range};
+use std::ops::Range;
 
-use ariadne::{Report, ReportKind, Color, Label, Fmt, ReportBuilder, Source};
+use ariadne::{Report, ReportKind, Color, Label, Fmt, ReportBuilder};
 use chumsky::{prelude::Simple, error::SimpleReason};
 use yansi::Paint;
 
-use crate::{lexer::token::Token, parser::types::{typelist::TypeList, typ::Type}};
+use crate::{lexer::token::Token, parser::{types::{typelist::TypeList, typ::Type}, signature_parser::TypedSignature}, source::{SourceId, Span, with_sources}};
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     Lexer { 
-        inner: Vec<Simple<char>> 
+        source: SourceId,
+
+        inner: Vec<Simple<char>>,
+
+        /// Input skipped while recovering from the errors
+        recovered: Vec<Range<usize>>
     },
 
     General {
@@ -55,29 +61,105 @@ pub enum Error {
         expected: TypeList,
         got: TypeList
     },
+
+    InvalidInterpolation {
+        token: Token,
+        got: TypeList
+    },
+
+    InvalidRecord {
+        token: Token,
+        msg: String
+    },
+
+    InvalidUnion {
+        token: Token,
+        msg: String
+    },
+
+    InvalidMatch {
+        token: Token,
+        msg: String
+    },
+
+    InvalidTypeAlias {
+        token: Token,
+        msg: String
+    },
+
+    InvalidTry {
+        token: Token,
+        msg: String
+    },
+
+    /// The type on top, if there is one
+    InvalidUnpack {
+        token: Token,
+        got: Option<Type>
+    },
+
+    InvalidSignature {
+        token: Token,
+        msg: String
+    },
+
+    InvalidClass {
+        token: Token,
+        msg: String
+    },
+
+    InvalidInstance {
+        token: Token,
+        msg: String
+    },
+
+    /// The token of the call which needs the instance
+    MissingInstance {
+        token: Token,
+        constraint: Type
+    },
+
+    /// A constraint on a type variable, which the signature does not have
+    UnsolvedConstraint {
+        token: Token,
+        constraint: Type
+    },
+
+    IncompatibleMatchArms {
+        token: Token,
+        variant: String,
+        expected: TypedSignature,
+        got: TypedSignature
+    },
 }
 
 
 impl Error {
-    pub fn report(&self, src: String) {
-        let print = |r: ReportBuilder<_>| r
+    pub fn report(&self) {
+        let print = |r: ReportBuilder<_>| with_sources(|sources| r
             .finish()
-            .eprint(Source::from(src.clone()))
-            .expect("Beeing able to print errors");
+            .eprint(sources)
+            .expect("Beeing able to print errors"));
 
         match self {
-            Error::Lexer { inner } => 
+            Error::Lexer { source, inner, recovered } => {
                 inner
                     .iter()
                     .for_each(|lex_err| 
-                        print(lexer_error_report(lex_err))),
+                        print(lexer_error_report(*source, lex_err)));
+
+                recovered
+                    .iter()
+                    .for_each(|range| 
+                        print(recovery_warning_report(Span::new(*source, range.clone()))));
+            },
 
             Error::General { message } => print(message_error_report(message.clone())),
 
-            Error::VariableNotFound { token: Token::Ident { value, range } } | 
-            Error::VariableNotFound { token: Token::GetIdent { value, range } } 
+            Error::VariableNotFound { token: Token::Ident { value, span } } | 
+            Error::VariableNotFound { token: Token::GetIdent { value, span } } 
             => print(simple_error_report(
-                range.clone(), 
+                span.clone(), 
                 format!(
                     "The variable {} is not defined at this point",
                     value.fg(Color::Cyan)
@@ -85,8 +167,8 @@ impl Error {
                 "this one".to_string()
             )),
 
-            Error::AssigmentEmptyStack { token: Token::Assigment { value, range } } => print(simple_error_report(
-                range.clone(), 
+            Error::AssigmentEmptyStack { token: Token::Assigment { value, span } } => print(simple_error_report(
+                span.clone(), 
                 format!(
                     "Cannot assign to {}, as the stack is empty at this point",
                     value.fg(Color::Cyan)
@@ -94,8 +176,8 @@ impl Error {
                 "this one".to_string()
             )),
 
-            Error::Reassigment { token: Token::Assigment { value, range } } => print(simple_error_report(
-                range.clone(), 
+            Error::Reassigment { token: Token::Assigment { value, span } } => print(simple_error_report(
+                span.clone(), 
                 format!(
                     "Cannot assign to {}, as it already assigned a value at this point",
                     value.fg(Color::Cyan)
@@ -104,7 +186,7 @@ impl Error {
             )),
 
             Error::WrongTypeInList { token, expected, got } => print(simple_error_report(
-                token.range().clone(), 
+                token.span().clone(), 
                 format!(
                     "This list of type {} cannot contain value of type {}",
                     expected.fg(Color::Cyan),
@@ -114,14 +196,14 @@ impl Error {
             )),
 
             Error::UnificationError { token, msg } => print(simple_error_report(
-                token.range().clone(), 
+                token.span().clone(), 
                 msg.clone(), 
                 "here".to_string()
             )),
 
             Error::WrongArguments { fname, token, expected, got } => {
                 let builder = simple_error_report(
-                    token.range().clone(), 
+                    token.span().clone(), 
                     format!(
                         "Function {} called with unexpected arguments",
                         fname.fg(Color::Cyan)
@@ -134,7 +216,7 @@ impl Error {
 
             Error::IncompatibleFunctionReturn { token, expected, got } => {
                 let builder = simple_error_report(
-                    token.range().clone(), 
+                    token.span().clone(), 
                     "Return stack of function does not match its signature".to_string(),
                     "here".to_string()
                 );
@@ -142,27 +224,130 @@ impl Error {
                 print(add_stack_comparison(builder, expected, got));
             },
 
+            Error::InvalidInterpolation { token, got } => print(
+                simple_error_report(
+                    token.span().clone(), 
+                    "Interpolated code has to result in exactly one str, num, int or bool".to_string(),
+                    "somewhere in this string".to_string()
+                )
+                .with_note(format!("\n\tGot:\n\t{}", got.fg(Color::Red)))
+            ),
+
+            Error::InvalidRecord { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this record".to_string()
+            )),
+
+            Error::InvalidUnion { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this union".to_string()
+            )),
+
+            Error::InvalidMatch { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this match".to_string()
+            )),
+
+            Error::InvalidTypeAlias { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this type alias".to_string()
+            )),
+
+            Error::InvalidTry { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "here".to_string()
+            )),
+
+            Error::InvalidUnpack { token, got } => {
+                let builder = simple_error_report(
+                    token.span().clone(), 
+                    "unpack expects a tuple on top of the stack".to_string(),
+                    "here".to_string()
+                );
+
+                match got {
+                    Some(got) => print(builder.with_note(format!("\n\tGot:\n\t{}", got.fg(Color::Red)))),
+
+                    None => print(builder.with_note("The stack is empty at this point"))
+                }
+            },
+
+            Error::InvalidSignature { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in the signature of this function".to_string()
+            )),
+
+            Error::InvalidClass { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this class".to_string()
+            )),
+
+            Error::InvalidInstance { token, msg } => print(simple_error_report(
+                token.span().clone(), 
+                msg.clone(), 
+                "in this instance".to_string()
+            )),
+
+            Error::MissingInstance { token, constraint } => print(simple_error_report(
+                token.span().clone(), 
+                format!("There is no instance {}", constraint.fg(Color::Red)),
+                "needed here".to_string()
+            )),
+
+            Error::UnsolvedConstraint { token, constraint } => print(
+                simple_error_report(
+                    token.span().clone(), 
+                    format!("The constraint {} cannot be satisfied", constraint.fg(Color::Red)),
+                    "needed here".to_string()
+                )
+                .with_note("Either the type has to be known here or the signature of the function needs the constraint")
+            ),
+
+            Error::IncompatibleMatchArms { token, variant, expected, got } => print(
+                simple_error_report(
+                    token.span().clone(), 
+                    format!(
+                        "The arm for {} has a different stack effect than the first one",
+                        variant.fg(Color::Cyan)
+                    ),
+                    "in this match".to_string()
+                )
+                .with_note(format!(
+                    "\n\tExpected:\n\t{}\n\n\tGot:\n\t{}",
+                    expected.fg(Color::Cyan),
+                    got.fg(Color::Red)
+                ))
+            ),
+
             _ => unimplemented!()
         }
     }
 }
 
-fn message_error_report<'a>(msg: String) -> ReportBuilder<'a, Range<usize>> {
-    Report::build(ReportKind::Error, (), 0)
+fn message_error_report<'a>(msg: String) -> ReportBuilder<'a, Span> {
+    // Without labels no source is ever looked at
+    Report::build(ReportKind::Error, SourceId::ANONYMOUS, 0)
         .with_message(msg)
 }
 
-fn simple_error_report<'a>(range: Range<usize>, msg: String, label: String) -> ReportBuilder<'a, Range<usize>> {
-    Report::build(ReportKind::Error, (), range.start)
+fn simple_error_report<'a>(span: Span, msg: String, label: String) -> ReportBuilder<'a, Span> {
+    Report::build(ReportKind::Error, span.source, span.start())
         .with_message(msg)
         .with_label(
-            Label::new(range)
+            Label::new(span)
                 .with_message(label)
                 .with_color(Color::Red)
         )
 }
 
-fn add_stack_comparison<'a>(builder: ReportBuilder<'a, Range<usize>>, expected: &TypeList, got: &TypeList) -> ReportBuilder<'a, Range<usize>> {
+fn add_stack_comparison<'a>(builder: ReportBuilder<'a, Span>, expected: &TypeList, got: &TypeList) -> ReportBuilder<'a, Span> {
     builder
         .with_note(
             format!(
@@ -173,9 +358,19 @@ fn add_stack_comparison<'a>(builder: ReportBuilder<'a, Range<usize>>, expected:
         )
 }
 
-fn lexer_error_report(err: &Simple<char>) -> ReportBuilder<Range<usize>> {
+fn recovery_warning_report<'a>(span: Span) -> ReportBuilder<'a, Span> {
+    Report::build(ReportKind::Warning, span.source, span.start())
+        .with_message("Input was skipped while recovering from an error")
+        .with_label(
+            Label::new(span)
+                .with_message("this was ignored")
+                .with_color(Color::Yellow)
+        )
+}
+
+fn lexer_error_report(source: SourceId, err: &Simple<char>) -> ReportBuilder<'_, Span> {
     let e = err.clone().map(|c| c.to_string());
-    let report = Report::build(ReportKind::Error, (), e.span().start);
+    let report = Report::build(ReportKind::Error, source, e.span().start);
 
     match e.reason() {
         SimpleReason::Unclosed { span, delimiter } => report
@@ -184,7 +379,7 @@ fn lexer_error_report(err: &Simple<char>) -> ReportBuilder<Range<usize>> {
                 delimiter.fg(Color::Yellow)
             ))
             .with_label(
-                Label::new(span.clone())
+                Label::new(Span::new(source, span.clone()))
                     .with_message(format!(
                         "Unclosed delimiter {}",
                         delimiter.fg(Color::Yellow)
@@ -192,7 +387,7 @@ fn lexer_error_report(err: &Simple<char>) -> ReportBuilder<Range<usize>> {
                     .with_color(Color::Yellow),
             )
             .with_label(
-                Label::new(e.span())
+                Label::new(Span::new(source, e.span()))
                     .with_message(format!(
                         "Must be closed before this {}",
                         e.found()
@@ -226,7 +421,7 @@ fn lexer_error_report(err: &Simple<char>) -> ReportBuilder<Range<usize>> {
                 }
             ))
             .with_label(
-                Label::new(e.span())
+                Label::new(Span::new(source, e.span()))
                     .with_message(format!(
                         "Unexpected token {}",
                         e.found()
@@ -237,7 +432,7 @@ fn lexer_error_report(err: &Simple<char>) -> ReportBuilder<Range<usize>> {
             ),
 
         SimpleReason::Custom(msg) => report.with_message(msg).with_label(
-            Label::new(e.span())
+            Label::new(Span::new(source, e.span()))
                 .with_message(format!("{}", msg.fg(Color::Red)))
                 .with_color(Color::Red),
         ),
@@ -1,100 +1,462 @@
+use std::{num::IntErrorKind, ops::Range};
+
 use chumsky::prelude::*;
 
-use super::{token::Token, sig_lexer::sig_lexer};
+use crate::source::{SourceId, Span};
+
+use super::{token::{Token, QuoteSegment}, sig_lexer::{sig_lexer, type_lexer, SignatureElement}};
 
 fn ident_lexer() -> impl Parser<char, String, Error = Simple<char>> + Clone {
     let punctuation = filter(|c: &char| {
         c.is_ascii_punctuation() && !['[', ']', '{', '}', ':', '(', ')', '"', '\'', '$'].contains(c)
     });
 
-    filter(|c: &char| c.is_alphabetic())
-            .or(punctuation)
+    // Identifiers end where a comment starts, e.g. in `x// comment`
+    let no_comment = just("//").or(just("/*")).not().rewind();
+
+    no_comment
+            .ignore_then(filter(|c: &char| c.is_alphabetic()).or(punctuation))
             .chain(
-                filter(|c: &char| c.is_alphanumeric())
-                    .or(punctuation)
+                no_comment
+                    .ignore_then(filter(|c: &char| c.is_alphanumeric()).or(punctuation))
                     .repeated(),
             )
             .collect::<String>()
 }
 
-pub fn lexer() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
+fn comment_lexer(source: SourceId) -> impl Parser<char, Token, Error = Simple<char>> + Clone {
+    let line_comment = just("//")
+        .then(filter(|c: &char| *c != '\n').repeated())
+        .ignored();
+
+    // Block comments may be nested, so commenting out code which
+    // already contains a block comment just works
+    let block_comment = recursive(|nested| 
+        just("/*")
+            .then(nested.or(just("*/").not().ignored()).repeated())
+            .then_ignore(just("*/"))
+            .ignored()
+    );
+
+    line_comment
+        .or(block_comment)
+        .labelled("comment")
+        .map_with_span(move |_, span| Token::Comment { span: Span::new(source, span) })
+}
+
+#[derive(Debug, PartialEq)]
+enum NumberLiteral {
+    Number(f64),
+    Integer(i64)
+}
+
+fn parse_number_literal(literal: &str) -> Result<NumberLiteral, String> {
+    let (sign, unsigned) = match literal.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", literal)
+    };
+
+    let (radix, digits) = if let Some(digits) = unsigned.strip_prefix("0x") {
+        (16, digits)
+    }
+    else if let Some(digits) = unsigned.strip_prefix("0b") {
+        (2, digits)
+    }
+    else if let Some(digits) = unsigned.strip_suffix('i') {
+        (10, digits)
+    }
+    else {
+        // Neither prefixed nor suffixed, so a plain num
+        let is_valid = unsigned.split('.').count() <= 2
+            && !unsigned.ends_with('.')
+            && unsigned.chars().all(|c| c.is_ascii_digit() || c == '_' || c == '.');
+
+        return match unsigned.replace('_', "").parse::<f64>() {
+            Ok(value) if is_valid => Ok(NumberLiteral::Number(if sign == "-" { -value } else { value })),
+
+            _ => Err(format!("Invalid number literal {literal}"))
+        }
+    };
+
+    if digits.is_empty() || digits.starts_with('_') {
+        return Err(format!("Invalid integer literal {literal}"));
+    }
+
+    i64::from_str_radix(&format!("{sign}{}", digits.replace('_', "")), radix)
+        .map(NumberLiteral::Integer)
+        .map_err(|err| match err.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => 
+                format!("Integer literal {literal} does not fit into an int"),
+
+            _ => format!("Invalid integer literal {literal}")
+        })
+}
+
+fn number_lexer(source: SourceId) -> impl Parser<char, Token, Error = Simple<char>> + Clone {
+    // Everything which starts like a number is lexed as one, this way typos
+    // like 0xFG or 12a are reported instead of being split into several tokens
+    just('-')
+        .or_not()
+        .chain::<char, _, _>(filter(|c: &char| c.is_ascii_digit()))
+        .chain::<char, _, _>(filter(|c: &char| c.is_alphanumeric() || *c == '_' || *c == '.').repeated())
+        .collect::<String>()
+        .labelled("number")
+        .validate(move |literal, range: Range<usize>, emit| {
+            let span = Span::new(source, range.clone());
+
+            match parse_number_literal(&literal) {
+                Ok(NumberLiteral::Number(value)) => Token::Number { value, span },
+
+                Ok(NumberLiteral::Integer(value)) => Token::Integer { value, span },
+
+                Err(msg) => {
+                    emit(Simple::custom(range, msg));
+                    Token::Number { value: 0.0, span }
+                }
+            }
+        })
+}
+
+fn escape_lexer() -> impl Parser<char, char, Error = Simple<char>> + Clone {
+    let simple = just('\\')
+        .or(just('/'))
+        .or(just('"'))
+        .or(just('{'))
+        .or(just('}'))
+        .or(just('n').to('\n'))
+        .or(just('t').to('\t'))
+        .or(just('r').to('\r'))
+        .or(just('0').to('\0'))
+        .map(Ok);
+
+    // \u{1F600}
+    let unicode = just('u')
+        .ignore_then(
+            filter(|c: &char| *c != '}' && *c != '"')
+                .repeated()
+                .collect::<String>()
+                .delimited_by(just('{'), just('}'))
+        )
+        .map(|hex| {
+            let is_valid = !hex.is_empty() && hex.len() <= 6;
+
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .filter(|_| is_valid)
+                .and_then(char::from_u32)
+                .ok_or(format!("Invalid unicode escape \\u{{{hex}}}"))
+        });
+
+    // \x7F, only ASCII so that strings stay valid UTF-8
+    let byte = just('x')
+        .ignore_then(
+            filter(|c: &char| c.is_ascii_hexdigit())
+                .repeated()
+                .exactly(2)
+                .collect::<String>()
+        )
+        .map(|hex| {
+            let byte = u8::from_str_radix(&hex, 16).unwrap();
+
+            if byte.is_ascii() { 
+                Ok(byte as char)
+            }
+            else {
+                Err(format!("Invalid escape \\x{hex}, only values up to \\x7F are allowed"))
+            }
+        });
+
+    let invalid = any()
+        .map(|c| Err(format!("Invalid escape sequence \\{c}")));
+
+    just('\\')
+        .ignore_then(simple.or(unicode).or(byte).or(invalid))
+        .labelled("escape character")
+        .validate(|escaped, span, emit| 
+            escaped.unwrap_or_else(|msg| {
+                emit(Simple::custom(span, msg));
+                char::REPLACEMENT_CHARACTER
+            }))
+}
+
+enum StringPart {
+    Char(char),
+    Code(Vec<Token>)
+}
+
+fn collect_segments(parts: Vec<StringPart>) -> Vec<QuoteSegment> {
+    let mut segments = Vec::new();
+
+    for part in parts {
+        match (part, segments.last_mut()) {
+            (StringPart::Char(c), Some(QuoteSegment::Text(text))) => text.push(c),
+
+            (StringPart::Char(c), _) => segments.push(QuoteSegment::Text(c.to_string())),
+
+            (StringPart::Code(tokens), _) => segments.push(QuoteSegment::Code(tokens)),
+        }
+    }
+
+    segments
+}
+
+/// Strings are interpolated, `{` starts code whose result is inserted. A literal brace is 
+/// written as `\{`, or in a raw string. Note that this changed the meaning of strings 
+/// written before interpolation existed, e.g. `"{x}"` is now the value of `x`
+fn string_lexer<P>(code: P, source: SourceId) -> impl Parser<char, Token, Error = Simple<char>> + Clone
+    where P: Parser<char, Token, Error = Simple<char>> + Clone
+{
+    let char_part = filter(|c| *c != '\\' && *c != '"' && *c != '{')
+        .or(escape_lexer())
+        .map(StringPart::Char);
+
+    // "total: {x}"
+    let code_part = code
+        .padded()
+        .repeated()
+        .delimited_by(just('{'), just('}'))
+        .labelled("interpolation")
+        .map(StringPart::Code);
+
+    let string = just('"')
+        .ignore_then(char_part.or(code_part).repeated())
+        .then_ignore(just('"'))
+        .map(collect_segments)
+        .map_with_span(move |segments, range| {
+            let span = Span::new(source, range);
+
+            match &segments[..] {
+                [] => Token::Quote { value: String::new(), span },
+
+                [QuoteSegment::Text(text)] => Token::Quote { value: text.clone(), span },
+
+                _ => Token::InterpolatedQuote { segments, span }
+            }
+        });
+
+    // Raw strings may span multiple lines and do not support escapes, 
+    // r#"..."# may even contain quotes
+    let raw = just('"')
+        .ignore_then(take_until(just('"')))
+        .map(|(chars, _)| chars);
+
+    let hashed_raw = just("#\"")
+        .ignore_then(take_until(just("\"#")))
+        .map(|(chars, _)| chars);
+
+    let raw_string = just('r')
+        .ignore_then(raw.or(hashed_raw))
+        .collect::<String>()
+        .map_with_span(move |str, span| 
+            Token::Quote { value: str, span: Span::new(source, span) });
+
+    string
+        .or(raw_string)
+        .labelled("string")
+}
+
+pub fn lexer(source: SourceId) -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
     let pad = one_of(" \t").repeated();
 
     recursive::<char, Token, _, _, Simple<char>>(|rec| {
-        let number = just('-')
-            .or_not()
-            .chain::<char, _, _>(text::int(10))
-            .chain::<char, _, _>(just('.').chain(text::digits(10)).or_not().flatten())
-            .collect::<String>()
-            .labelled("number")
-            .map_with_span(|str, span| 
-                Token::Number { value: str.parse().unwrap(), range: span });
+        let boolean = ident_lexer()
+            .try_map(|str, span| match str.as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(Simple::custom(span, "not a boolean"))
+            })
+            .labelled("boolean")
+            .map_with_span(move |value, span| 
+                Token::Bool { value, span: Span::new(source, span) });
+
+        // Idents only starting with a keyword, like try-parse, are normal ones
+        let keyword = |word: &'static str| ident_lexer()
+            .try_map(move |str, span| if str == word {
+                Ok(())
+            }
+            else {
+                Err(Simple::custom(span, format!("not {word}")))
+            })
+            .labelled(word);
+
+        let try_word = keyword("try")
+            .map_with_span(move |_, span| 
+                Token::Try { span: Span::new(source, span) });
+
+        let unpack = keyword("unpack")
+            .map_with_span(move |_, span| 
+                Token::Unpack { span: Span::new(source, span) });
 
         let ident = ident_lexer()
             .labelled("identifier")
-            .map_with_span(|str, span| 
-                Token::Ident { value: str, range: span });
+            .map_with_span(move |str, span| 
+                Token::Ident { value: str, span: Span::new(source, span) });
 
         let get_ident = just(':')
                 .ignore_then(ident_lexer())
                 .labelled("get-identifier")
-                .map_with_span(|str, span| 
-                    Token::GetIdent { value: str, range: span });
+                .map_with_span(move |str, span| 
+                    Token::GetIdent { value: str, span: Span::new(source, span) });
 
         let assigment = ident_lexer()
             .then_ignore(just(':'))
             .labelled("assigment")
-            .map_with_span(|str, span| 
-                Token::Assigment { value: str, range: span });
-
-        let escape = just('\\')
-            .ignore_then(
-                just('\\')
-                    .or(just('/'))
-                    .or(just('"'))
-                    .or(just('n').to('\n')),
-            )
-            .labelled("escape character");
-
-        let string = just('"')
-            .ignore_then(filter(|c| *c != '\\' && *c != '"').or(escape).repeated())
-            .then_ignore(just('"'))
-            .collect::<String>()
-            .labelled("string")
-            .map_with_span(|str, span| 
-                Token::Quote { value: str, range: span });
+            .map_with_span(move |str, span| 
+                Token::Assigment { value: str, span: Span::new(source, span) });
 
+        // The outer padding allows whitespace in empty ones
         let block = rec
             .clone()
             .padded()
             .repeated()
+            .padded()
             .delimited_by(just('['), just(']'))
             .labelled("block")
-            .map_with_span(|list, span| 
-                Token::List { value: list, range: span });
+            .map_with_span(move |list, span| 
+                Token::List { value: list, span: Span::new(source, span) });
+
+        // Function signatures are tried first, as they look alike
+        let tuple = rec
+            .clone()
+            .padded()
+            .repeated()
+            .padded()
+            .delimited_by(just('('), just(')'))
+            .labelled("tuple")
+            .map_with_span(move |elems, span| 
+                Token::Tuple { value: elems, span: Span::new(source, span) });
 
         let function_body = rec
             .clone()
             .padded()
             .repeated()
+            .padded()
             .delimited_by(just('{'), just('}'));
 
         let function = sig_lexer()
             .padded()
-            .then(function_body)
-            .map_with_span(|(sig, body), span| 
-                Token::Function { sig, body, range: span });
+            .or_not()
+            .then(function_body.clone())
+            .map_with_span(move |(sig, body), span| 
+                Token::Function { sig, body, span: Span::new(source, span) });
+
+        // Declarations look like `<keyword> <name> [ ... ]`, generic ones have
+        // their parameters right after the name: `<keyword> <name>['a] [ ... ]`
+        let declaration = |keyword| just(keyword)
+            .ignore_then(one_of(" \t").repeated().at_least(1))
+            .ignore_then(type_lexer())
+            .try_map(|head, span| match head {
+                SignatureElement::Kind(name, params) => Ok((name, params)),
+
+                SignatureElement::Variable(_) | SignatureElement::Row(_) => 
+                    Err(Simple::custom(span, "Expected the name of the declared type"))
+            })
+            .then_ignore(one_of(" \t").repeated().at_least(1));
+
+        let field = ident_lexer()
+            .then_ignore(just(':').padded())
+            .then(type_lexer());
+
+        let record = declaration("record")
+            .then(
+                field
+                    .padded()
+                    .repeated()
+                    .padded()
+                    .delimited_by(just('['), just(']'))
+            )
+            .labelled("record")
+            .map_with_span(move |((name, params), fields), span| 
+                Token::Record { name, params, fields, span: Span::new(source, span) });
+
+        let union = declaration("union")
+            .then(
+                type_lexer()
+                    .padded()
+                    .repeated()
+                    .padded()
+                    .delimited_by(just('['), just(']'))
+            )
+            .labelled("union")
+            .map_with_span(move |((name, params), variants), span| 
+                Token::Union { name, params, variants, span: Span::new(source, span) });
+
+        let type_alias = declaration("type")
+            .then(type_lexer())
+            .labelled("type alias")
+            .map_with_span(move |((name, params), typ), span| 
+                Token::TypeAlias { name, params, typ, span: Span::new(source, span) });
+
+        let method_sig = ident_lexer()
+            .then_ignore(just(':').padded())
+            .then(sig_lexer());
+
+        let class = declaration("class")
+            .then(
+                method_sig
+                    .padded()
+                    .repeated()
+                    .padded()
+                    .delimited_by(just('['), just(']'))
+            )
+            .labelled("class")
+            .map_with_span(move |((name, params), methods), span| 
+                Token::Class { name, params, methods, span: Span::new(source, span) });
+
+        let method_body = ident_lexer()
+            .then_ignore(just(':').padded())
+            .then(function_body.clone());
+
+        let instance = declaration("instance")
+            .then(
+                method_body
+                    .padded()
+                    .repeated()
+                    .padded()
+                    .delimited_by(just('['), just(']'))
+            )
+            .labelled("instance")
+            .map_with_span(move |((class, params), methods), span| 
+                Token::Instance { class, params, methods, span: Span::new(source, span) });
+
+        let arm = ident_lexer()
+            .then_ignore(one_of(" \t").repeated())
+            .then(function_body.clone());
+
+        let match_arms = just("match")
+            .ignore_then(one_of(" \t").repeated())
+            .ignore_then(
+                arm
+                    .padded()
+                    .repeated()
+                    .padded()
+                    .delimited_by(just('['), just(']'))
+            )
+            .labelled("match")
+            .map_with_span(move |arms, span| 
+                Token::Match { arms, span: Span::new(source, span) });
 
         let newline = just('\n')
             .labelled("newline")
             .map(|_| Token::Newline);
 
-        string
-            .or(number)
+        comment_lexer(source)
+            .or(string_lexer(rec.clone(), source))
+            .or(number_lexer(source))
+            .or(record)
+            .or(union)
+            .or(type_alias)
+            .or(class)
+            .or(instance)
+            .or(match_arms)
             .or(assigment)
+            .or(boolean)
+            .or(try_word)
+            .or(unpack)
             .or(ident)
             .or(get_ident)
             .or(function)
+            .or(tuple)
             .or(block)
             .or(newline)
     })
@@ -103,3 +465,126 @@ pub fn lexer() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
     .repeated()
     .then_ignore(end())
 }
+
+#[cfg(test)]
+mod tests {
+    use chumsky::{Parser, prelude::end, error::SimpleReason};
+
+    use crate::{lexer::token::Token, source::SourceId};
+
+    use super::{parse_number_literal, escape_lexer, lexer, NumberLiteral::{self, *}};
+
+    #[test]
+    fn number_literals() {
+        let cases: [(&str, NumberLiteral); 12] = [
+            ("12", Number(12.0)),
+            ("-1.5", Number(-1.5)),
+            ("1_000.25", Number(1000.25)),
+            ("12i", Integer(12)),
+            ("-7i", Integer(-7)),
+            ("1_000_000i", Integer(1_000_000)),
+            ("0x1F", Integer(31)),
+            ("0xff_ff", Integer(0xffff)),
+            ("-0x10", Integer(-16)),
+            ("0b101", Integer(5)),
+            ("0b1111_0000", Integer(0xf0)),
+            ("0x7fffffffffffffff", Integer(i64::MAX))
+        ];
+
+        for (literal, expected) in cases {
+            assert_eq!(parse_number_literal(literal), Ok(expected), "{literal}");
+        }
+    }
+
+    #[test]
+    fn invalid_number_literals() {
+        let cases = [
+            ("0b2", "Invalid integer literal 0b2"),
+            ("0xFG", "Invalid integer literal 0xFG"),
+            ("0x", "Invalid integer literal 0x"),
+            ("0b_1", "Invalid integer literal 0b_1"),
+            ("12a", "Invalid number literal 12a"),
+            ("1.2.3", "Invalid number literal 1.2.3"),
+            ("1.", "Invalid number literal 1."),
+            ("1.5i", "Invalid integer literal 1.5i"),
+            ("0x8000000000000000", "Integer literal 0x8000000000000000 does not fit into an int"),
+            ("99999999999999999999i", "Integer literal 99999999999999999999i does not fit into an int")
+        ];
+
+        for (literal, expected) in cases {
+            assert_eq!(parse_number_literal(literal), Err(expected.to_string()), "{literal}");
+        }
+    }
+
+    fn escape(src: &str) -> Result<char, Vec<String>> {
+        escape_lexer()
+            .then_ignore(end())
+            .parse(src)
+            .map_err(|errs| errs
+                .into_iter()
+                .map(|err| match err.reason() {
+                    SimpleReason::Custom(msg) => msg.clone(),
+
+                    reason => format!("{reason:?}")
+                })
+                .collect())
+    }
+
+    #[test]
+    fn escapes() {
+        let cases = [
+            (r"\n", '\n'),
+            (r"\t", '\t'),
+            (r"\0", '\0'),
+            (r#"\""#, '"'),
+            (r"\{", '{'),
+            (r"\\", '\\'),
+            (r"\x41", 'A'),
+            (r"\x7F", '\x7F'),
+            (r"\u{41}", 'A'),
+            (r"\u{1F600}", '\u{1F600}'),
+            (r"\u{10FFFF}", '\u{10FFFF}')
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(escape(src), Ok(expected), "{src}");
+        }
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let cases = [
+            (r"\x80", "Invalid escape \\x80, only values up to \\x7F are allowed"),
+            (r"\xFF", "Invalid escape \\xFF, only values up to \\x7F are allowed"),
+            (r"\u{}", "Invalid unicode escape \\u{}"),
+            (r"\u{D800}", "Invalid unicode escape \\u{D800}"),
+            (r"\u{1234567}", "Invalid unicode escape \\u{1234567}"),
+            (r"\u{zz}", "Invalid unicode escape \\u{zz}"),
+            (r"\q", "Invalid escape sequence \\q")
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(escape(src), Err(vec![expected.to_string()]), "{src}");
+        }
+    }
+
+    #[test]
+    fn braces_in_strings() {
+        let lex = |src: &str| lexer(SourceId::ANONYMOUS).parse(src).unwrap();
+
+        assert!(matches!(&lex(r#""a \{b}""#)[..], [Token::Quote { value, .. }] if value == "a {b}"));
+        assert!(matches!(&lex(r#"r"a {b}""#)[..], [Token::Quote { value, .. }] if value == "a {b}"));
+        assert!(matches!(&lex(r#""a {b}""#)[..], [Token::InterpolatedQuote { .. }]));
+    }
+
+    #[test]
+    fn comments_after_identifiers() {
+        let lex = |src: &str| lexer(SourceId::ANONYMOUS).parse(src).unwrap();
+
+        assert!(matches!(&lex("x// comment")[..], [Token::Ident { value, .. }, Token::Comment { .. }] if value == "x"));
+        assert!(matches!(&lex("x/* a */y")[..], [Token::Ident { value: x, .. }, Token::Comment { .. }, Token::Ident { value: y, .. }] if x == "x" && y == "y"));
+
+        // A slash on its own still belongs to the identifier
+        assert!(matches!(&lex("a/b")[..], [Token::Ident { value, .. }] if value == "a/b"));
+    }
+}
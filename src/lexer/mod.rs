@@ -1,26 +1,220 @@
-use chumsky::Parser;
+use std::ops::Range;
 
-use crate::error::Error;
+use chumsky::{Parser, prelude::Simple, error::SimpleReason};
 
-use self::{token::Token, ez_lexer::lexer};
+use crate::{error::Error, source::{SourceId, source_content}};
+
+use self::{token::{Token, QuoteSegment}, ez_lexer::lexer};
 
 mod ez_lexer;
 pub mod sig_lexer;
 pub mod token;
 
-fn preprocess_tokens(tokens: Vec<Token>) -> Vec<Token> {
+fn strip_comments(tokens: Vec<Token>) -> Vec<Token> {
     tokens
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Comment { .. }))
+        .map(|token| match token {
+            Token::List { value, span } => 
+                Token::List { value: strip_comments(value), span },
+
+            Token::Tuple { value, span } => 
+                Token::Tuple { value: strip_comments(value), span },
+
+            Token::Function { sig, body, span } => 
+                Token::Function { sig, body: strip_comments(body), span },
+
+            Token::Instance { class, params, methods, span } => {
+                let methods = methods
+                    .into_iter()
+                    .map(|(method, body)| (method, strip_comments(body)))
+                    .collect();
+
+                Token::Instance { class, params, methods, span }
+            },
+
+            Token::Match { arms, span } => {
+                let arms = arms
+                    .into_iter()
+                    .map(|(variant, body)| (variant, strip_comments(body)))
+                    .collect();
+
+                Token::Match { arms, span }
+            },
+
+            Token::InterpolatedQuote { segments, span } => {
+                let segments = segments
+                    .into_iter()
+                    .map(|segment| match segment {
+                        QuoteSegment::Code(code) => QuoteSegment::Code(strip_comments(code)),
+
+                        _ => segment
+                    })
+                    .collect();
+
+                Token::InterpolatedQuote { segments, span }
+            },
+
+            _ => token
+        })
+        .collect()
+}
+
+fn preprocess_tokens(tokens: Vec<Token>) -> Vec<Token> {
+    strip_comments(tokens)
         .split(|token| matches!(token, Token::Newline))
         .rev()
         .flat_map(|vec| vec.to_vec())
         .collect()
 }
 
-pub fn lex(src: String) -> Result<Vec<Token>, Error> {
-    let (tokens, errs) = lexer().parse_recovery_verbose(src);
+// The lexer recovers from errors by skipping input until it is able to
+// lex the next token, so everything between the token before and the token
+// after an error was skipped. Custom errors are emitted for tokens which 
+// were lexed, but are invalid nonetheless - nothing is skipped for them
+fn recovered_regions(src: &str, tokens: &[Token], errs: &[Simple<char>]) -> Vec<Range<usize>> {
+    let chars: Vec<char> = src.chars().collect();
+
+    let mut ranges: Vec<&Range<usize>> = Vec::new();
+    token_ranges(tokens, &mut ranges);
+
+    let mut regions: Vec<Range<usize>> = Vec::new();
+
+    let recovered_errs = errs
+        .iter()
+        .filter(|err| !matches!(err.reason(), SimpleReason::Custom(_)));
+
+    for err in recovered_errs {
+        let pos = err.span().start;
+
+        let mut start = ranges
+            .iter()
+            .filter(|range| range.end <= pos)
+            .map(|range| range.end)
+            .max()
+            .unwrap_or(0);
+
+        let mut end = ranges
+            .iter()
+            .filter(|range| range.start > pos)
+            .map(|range| range.start)
+            .min()
+            .unwrap_or(chars.len());
+
+        while start < end && chars[start].is_whitespace() { start += 1; }
+        while end > start && chars[end - 1].is_whitespace() { end -= 1; }
+
+        if start < end && !regions.contains(&(start..end)) {
+            regions.push(start..end);
+        }
+    }
+
+    regions
+}
+
+// Errors inside of lists, functions and the like are between the tokens nested in them
+fn token_ranges<'t>(tokens: &'t [Token], ranges: &mut Vec<&'t Range<usize>>) {
+    for token in tokens {
+        if matches!(token, Token::Newline) {
+            continue
+        }
+
+        ranges.push(&token.span().range);
+
+        match token {
+            Token::List { value, .. } | Token::Tuple { value, .. } => token_ranges(value, ranges),
+
+            Token::Function { body, .. } => token_ranges(body, ranges),
+
+            Token::Match { arms, .. } => arms
+                .iter()
+                .for_each(|(_, body)| token_ranges(body, ranges)),
+
+            Token::Instance { methods, .. } => methods
+                .iter()
+                .for_each(|(_, body)| token_ranges(body, ranges)),
+
+            Token::InterpolatedQuote { segments, .. } => segments
+                .iter()
+                .for_each(|segment| if let QuoteSegment::Code(code) = segment {
+                    token_ranges(code, ranges)
+                }),
+
+            _ => ()
+        }
+    }
+}
+
+/// Lexes a source registered in the source database
+pub fn lex(source: SourceId) -> Result<Vec<Token>, Error> {
+    let src = source_content(source);
+    let (tokens, errs) = lexer(source).parse_recovery_verbose(src.clone());
+
+    match tokens {
+        Some(tokens) if errs.is_empty() => Ok(preprocess_tokens(tokens)),
+
+        Some(tokens) => Err(Error::Lexer { 
+            source,
+            recovered: recovered_regions(&src, &tokens, &errs),
+            inner: errs
+        }),
+
+        None => Err(Error::Lexer { source, inner: errs, recovered: Vec::new() })
+    }
+}
+
+/// Lexes the source without any preprocessing, tokens are returned
+/// in the order they appear in the source and comments are kept.
+/// As this is meant for highlighting, errors are ignored as long as the 
+/// lexer is able to recover from them
+pub fn lex_raw(src: String) -> Result<Vec<Token>, Error> {
+    let source = SourceId::ANONYMOUS;
+    let (tokens, errs) = lexer(source).parse_recovery_verbose(src);
+
+    tokens.ok_or(Error::Lexer { source, inner: errs, recovered: Vec::new() })
+}
+
+/// If the source can only be lexed once more input follows, e.g. when
+/// a list, function, signature or string was not closed yet
+pub fn is_unfinished(src: String) -> bool {
+    let len = src.chars().count();
+    let (_, errs) = lexer(SourceId::ANONYMOUS).parse_recovery_verbose(src);
+
+    errs.iter().any(|err| 
+        err.found().is_none() 
+            && err.span().start >= len
+            && !matches!(err.reason(), SimpleReason::Custom(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, source::add_source};
+
+    use super::{lex, sig_lexer::lex_signature};
+
+    fn recovered(src: &str) -> Vec<String> {
+        match lex(add_source("test", src.to_string())) {
+            Err(Error::Lexer { recovered, .. }) => recovered
+                .into_iter()
+                .map(|range| src.chars().skip(range.start).take(range.len()).collect())
+                .collect(),
+
+            _ => panic!("{src} is lexed without errors")
+        }
+    }
 
-    // TODO Need to check for errors
+    #[test]
+    fn nested_recovered_regions() {
+        assert_eq!(recovered("1 2 ] 3"), ["]"]);
+        assert_eq!(recovered("[1 2] ] { 3 }"), ["]"]);
+        assert_eq!(recovered("{ [1] } ] (2 \"a{ 1 }\")"), ["]"]);
+    }
 
-    tokens.map(preprocess_tokens)
-        .ok_or(Error::Lexer { inner: errs })
+    #[test]
+    fn signatures_with_errors() {
+        assert!(lex_signature("( num -- num )").is_ok());
+        assert!(lex_signature("( num ] -- num )").is_err());
+        assert!(lex_signature("( num -- num").is_err());
+        assert!(lex_signature("( num -- num ) num").is_err());
+    }
 }
@@ -1,9 +1,10 @@
 use chumsky::prelude::*;
 
-use crate::error::Error;
+use crate::{error::Error, source::add_source};
 
+/// The arguments, the returns and the constraints
 #[derive(Clone, Debug, PartialEq)]
-pub struct LexedSignature(Vec<SignatureElement>, Vec<SignatureElement>);
+pub struct LexedSignature(Vec<SignatureElement>, Vec<SignatureElement>, Vec<SignatureElement>);
 
 impl LexedSignature {
     pub fn get_args(&self) -> &Vec<SignatureElement> {
@@ -13,64 +14,119 @@ impl LexedSignature {
     pub fn get_returns(&self) -> &Vec<SignatureElement> {
         &self.1
     }
+
+    pub fn get_constraints(&self) -> &Vec<SignatureElement> {
+        &self.2
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum SignatureElement {
     Kind(String, Vec<SignatureElement>),
-    Variable(String)
+    Variable(String),
+
+    /// Only ever the first element of a list
+    Row(String)
 }
 
-fn ident_lexer() -> impl Parser<char, String, Error = Simple<char>> + Clone {
-    let punctuation = filter(|c: &char| {
+/// Names of types, which may appear in signatures
+pub fn type_name_lexer() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    let is_punctuation = |c: &char| {
         c.is_ascii_punctuation() && !['[', ']', '{', '}', ':', '(', ')', '"', '\'', '$', '-'].contains(c)
-    });
+    };
 
-    filter(|c: &char| c.is_alphabetic())
-            .or(punctuation)
+    // A leading dot would be mistaken for a row variable
+    filter(move |c: &char| c.is_alphabetic() || (is_punctuation(c) && *c != '.'))
             .chain(
-                filter(|c: &char| c.is_alphanumeric())
-                    .or(punctuation)
+                filter(move |c: &char| c.is_alphanumeric() || is_punctuation(c))
                     .repeated(),
             )
             .collect::<String>()
 }
 
-pub fn sig_lexer() -> impl Parser<char, LexedSignature, Error = Simple<char>> + Clone {
-    let elem = recursive(|func|{
+// A row variable may only stand for the bottom of a stack: ..s 'a 'b
+fn with_row<P>(elems: P) -> impl Parser<char, Vec<SignatureElement>, Error = Simple<char>> + Clone
+    where P: Parser<char, Vec<SignatureElement>, Error = Simple<char>> + Clone
+{
+    let row = just("..")
+        .ignore_then(type_name_lexer())
+        .map(|name| SignatureElement::Row(format!("..{name}")))
+        .padded();
+
+    row.or_not()
+        .then(elems)
+        .map(|(row, mut elems)| {
+            if let Some(row) = row {
+                elems.insert(0, row);
+            }
+
+            elems
+        })
+}
+
+/// A single type, e.g. `'a`, `list[num]` or `pair['a]['b]`
+pub fn type_lexer() -> impl Parser<char, SignatureElement, Error = Simple<char>> + Clone {
+    recursive(|func|{
         let var = just('\'')
-            .ignore_then(ident_lexer())
+            .ignore_then(type_name_lexer())
             .map(SignatureElement::Variable);
 
-        let polytypes = func.padded()
+        // Both pair[num str] and pair[num][str] work, the latter is how types are printed
+        let polytypes = with_row(func.padded().repeated())
+            .delimited_by(just('['), just(']'))
             .repeated()
-            .delimited_by(just('['), just(']'));
+            .flatten();
         
-        let kind = ident_lexer()
-            .then(polytypes.or_not())
-            .map(|(name, typs)| SignatureElement::Kind(name, typs.unwrap_or_default()));
+        let kind = type_name_lexer()
+            .then(polytypes)
+            .map(|(name, typs)| SignatureElement::Kind(name, typs));
 
         var.or(kind.clone())
-    });
+    })
+}
+
+pub fn sig_lexer() -> impl Parser<char, LexedSignature, Error = Simple<char>> + Clone {
+    let side = with_row(type_lexer().padded().repeated()).or_not();
+
+    // Like in ( Num['a] => 'a 'a -- 'a )
+    let constraints = type_lexer()
+        // The arrow would be a valid type name
+        .try_map(|elem, span| match elem {
+            SignatureElement::Kind(name, _) if name == "=>" => Err(Simple::custom(span, "Expected a constraint")),
 
-    let side = elem.padded().repeated().or_not();
+            elem => Ok(elem)
+        })
+        .padded()
+        .repeated()
+        .at_least(1)
+        .then_ignore(just("=>").padded())
+        .or_not();
 
-    side.clone()
+    constraints
+        .then(side.clone())
         .then_ignore(just("--").padded())
         .then(side)
         .delimited_by(just("("), just(")"))
-        .map(|(args, ret)| LexedSignature(
+        .map(|((constraints, args), ret)| LexedSignature(
             args.unwrap_or_default(), 
-            ret.unwrap_or_default()
+            ret.unwrap_or_default(),
+            constraints.unwrap_or_default()
         ))
 }
 
 pub fn lex_signature(src: &str) -> Result<LexedSignature, Error> {
-    let (result, errs) = sig_lexer().parse_recovery_verbose(src.to_string());
+    let (result, errs) = sig_lexer()
+        .padded()
+        .then_ignore(end())
+        .parse_recovery_verbose(src.to_string());
 
     match result {
-        Some(sig) => Ok(sig),
+        Some(sig) if errs.is_empty() => Ok(sig),
 
-        None => Err(Error::Lexer { inner: errs })
+        _ => Err(Error::Lexer { 
+            source: add_source("<signature>", src.to_string()),
+            inner: errs, 
+            recovered: Vec::new() 
+        })
     }
 }
\ No newline at end of file
@@ -1,35 +1,92 @@
-use std::ops::Range;
+use crate::source::Span;
 
-use super::sig_lexer::LexedSignature;
+use super::sig_lexer::{LexedSignature, SignatureElement};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
-    Number { value: f64, range: Range<usize> },
-    Quote { value: String, range: Range<usize> },
-    Ident { value: String, range: Range<usize> },
-    GetIdent { value: String, range: Range<usize> },
-    Assigment { value: String, range: Range<usize> },
-    List { value: Vec<Token>, range: Range<usize> },
-    Function { sig: LexedSignature, body: Vec<Token>, range: Range<usize> },
+    Number { value: f64, span: Span },
+    Integer { value: i64, span: Span },
+    Bool { value: bool, span: Span },
+    Quote { value: String, span: Span },
+    InterpolatedQuote { segments: Vec<QuoteSegment>, span: Span },
+    Ident { value: String, span: Span },
+    GetIdent { value: String, span: Span },
+    Assigment { value: String, span: Span },
+    List { value: Vec<Token>, span: Span },
+    /// `(1 "one")`, packs the values into a tuple
+    Tuple { value: Vec<Token>, span: Span },
+    /// Without a signature, it is inferred from the body
+    Function { sig: Option<LexedSignature>, body: Vec<Token>, span: Span },
+    /// `record point [x: num y: num]`, the fields keep their order. The 
+    /// parameters of generic records follow the name: `record pair['a]['b] [...]`
+    Record { name: String, params: Vec<SignatureElement>, fields: Vec<(String, SignatureElement)>, span: Span },
+    /// `union shape [circle[num] rect[num num] empty]`, the payload of a variant 
+    /// are the types in its brackets
+    Union { name: String, params: Vec<SignatureElement>, variants: Vec<SignatureElement>, span: Span },
+    /// `type strings list[str]` or `type pred['a] fun[arg['a]][ret[bool]]`
+    TypeAlias { name: String, params: Vec<SignatureElement>, typ: SignatureElement, span: Span },
+    /// `class Show['a] [show: ( 'a -- str )]`
+    Class { name: String, params: Vec<SignatureElement>, methods: Vec<(String, LexedSignature)>, span: Span },
+    /// `instance Show[point] [show: { ... }]`, the params contain the type of the instance
+    Instance { class: String, params: Vec<SignatureElement>, methods: Vec<(String, Vec<Token>)>, span: Span },
+    /// `match [circle { ... } rect { ... } empty { ... }]`, one quotation per variant
+    Match { arms: Vec<(String, Vec<Token>)>, span: Span },
+    /// Unwraps the option or result on top, returns early from the function on failure
+    Try { span: Span },
+    /// Pushes the elements of the tuple on top
+    Unpack { span: Span },
+    Comment { span: Span },
     Newline
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuoteSegment {
+    Text(String),
+    Code(Vec<Token>)
+}
+
 impl Token {
-    pub fn range(&self) -> &Range<usize> {
+    pub fn span(&self) -> &Span {
         match &self {
-            Token::Number { range, .. } => range,
+            Token::Number { span, .. } => span,
+
+            Token::Integer { span, .. } => span,
+
+            Token::Bool { span, .. } => span,
+
+            Token::Quote { span, .. } => span,
+
+            Token::InterpolatedQuote { span, .. } => span,
+
+            Token::Ident { span, .. } => span,
+
+            Token::GetIdent { span, .. } => span,
+
+            Token::Assigment { span, .. } => span,
+
+            Token::List { span, .. } => span,
+
+            Token::Tuple { span, .. } => span,
+
+            Token::Function { span, .. } => span,
+
+            Token::Record { span, .. } => span,
+
+            Token::Union { span, .. } => span,
+
+            Token::TypeAlias { span, .. } => span,
 
-            Token::Quote { range, .. } => range,
+            Token::Class { span, .. } => span,
 
-            Token::Ident { range, .. } => range,
+            Token::Instance { span, .. } => span,
 
-            Token::GetIdent { range, .. } => range,
+            Token::Match { span, .. } => span,
 
-            Token::Assigment { range, .. } => range,
+            Token::Try { span } => span,
 
-            Token::List { range, .. } => range,
+            Token::Unpack { span } => span,
 
-            Token::Function { range, .. } => range,
+            Token::Comment { span } => span,
 
             Token::Newline => unreachable!(),
         }
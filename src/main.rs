@@ -1,3 +1,6 @@
+// The rich error enum is passed around by value on purpose
+#![allow(clippy::result_large_err)]
+
 use clap::Parser;
 use codegen::compiler::Compiler;
 use codegen::jit::Jit;
@@ -12,6 +15,7 @@ mod codegen;
 mod config;
 mod debug_printer;
 mod stdlib;
+mod source;
 mod code_graph;
 
 #[macro_use]
@@ -2,17 +2,47 @@ pub mod signature_parser;
 pub mod node;
 pub mod types;
 
-use crate::{lexer::token::Token, error::Error};
+use ariadne::{Color, Fmt};
 
-use self::{node::{Node, Literal}, types::{*, type_env::TypeEnv, typelist::TypeList, typ::Type}, signature_parser::TypedSignature};
+use crate::{lexer::{token::{Token, QuoteSegment}, sig_lexer::SignatureElement}, error::Error};
+
+use self::{node::{Node, Literal, InterpolationPart}, types::{*, type_env::TypeEnv, typelist::{TypeList, RowVar}, typ::Type, substitution::Substitution, scheme::Scheme, record::Record, tagged_union::Union, alias::Alias, class::{Class, Instance}}, signature_parser::{TypedSignature, build_type}};
+
+/// Parses a whole program. Afterwards the types in the AST, on the stack and of the
+/// bindings are as concrete as they can be, so the substitution is not needed anymore
+pub fn parse_program(tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>, Error> {
+    let nodes = parse(tokens, type_env)?;
+    solve_constraints(type_env, false)?;
+
+    let nodes = nodes
+        .iter()
+        .map(|node| node.substitute(&type_env.substitution))
+        .collect();
+
+    type_env.stack = type_env.apply_list(&type_env.stack);
+    type_env.bindings = type_env.bindings
+        .iter()
+        .map(|(name, scheme)| (name.clone(), scheme.substitute(&type_env.substitution)))
+        .collect();
+    type_env.substitution = Substitution::default();
+
+    Ok(nodes)
+}
 
 pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>, Error> {
     let mut typed_stack = Vec::new();
+
+    // Named functions which are bound before their definition, with the bindings they shadow
+    let mut declared: Vec<(String, Option<Scheme>)> = Vec::new();
     
     while !tokens.is_empty() {
         let token = &tokens.pop().unwrap();
 
-        let node = match token.clone() {
+        if is_named_function(token, tokens.last()) {
+            declare_named_functions(token, &tokens, type_env, &mut declared);
+        }
+
+        let mut node = match token.clone() {
             Token::Number { value, .. } =>
                 Node::Literal { 
                     typ: number_type(), 
@@ -20,6 +50,20 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
                     value: Literal::Number(value)
                 },
             
+            Token::Integer { value, .. } =>
+                Node::Literal { 
+                    typ: integer_type(), 
+                    token: token.clone(), 
+                    value: Literal::Integer(value)
+                },
+
+            Token::Bool { value, .. } =>
+                Node::Literal { 
+                    typ: bool_type(), 
+                    token: token.clone(), 
+                    value: Literal::Bool(value)
+                },
+            
             Token::Quote { value, .. } =>
                 Node::Literal { 
                     typ: quote_type(), 
@@ -27,39 +71,79 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
                     value: Literal::Quote(value)
                 },
             
+            Token::InterpolatedQuote { segments, .. } => {
+                let mut parts = Vec::new();
+
+                for segment in segments {
+                    let part = match segment {
+                        QuoteSegment::Text(text) => InterpolationPart::Text(text),
+
+                        QuoteSegment::Code(code) => 
+                            InterpolationPart::Code(parse_interpolated_code(token, code, type_env)?),
+                    };
+
+                    parts.push(part);
+                }
+
+                Node::Literal { 
+                    typ: quote_type(), 
+                    token: token.clone(),
+                    value: Literal::Interpolation(parts)
+                }
+            },
+            
             Token::Ident { ref value, .. } => {
+                // Every use gets its own type variables
                 let typ = type_env.bindings.get(value)
-                    .ok_or_else(|| Error::VariableNotFound { token: token.clone() })?;
+                    .ok_or_else(|| Error::VariableNotFound { token: token.clone() })?
+                    .clone()
+                    .instantiate(type_env);
 
                 if let Some((args, ret)) = typ.extract_function() {
                     Node::Call {
                         name: value.clone(), 
                         token: token.clone(),
                         arguments: args,
-                        returns: ret
+                        returns: ret,
+                        constraints: typ.function_constraints()
                     }
                 }
                 else {
                     Node::Variable { 
                         name: value.clone(), 
                         token: token.clone(), 
-                        typ: typ.clone()
+                        typ
                     }
                 }
             },
             
             Token::GetIdent { ref value, .. } => {
                 let typ = type_env.bindings.get(value)
-                    .ok_or_else(|| Error::VariableNotFound { token: token.clone() })?;
+                    .ok_or_else(|| Error::VariableNotFound { token: token.clone() })?
+                    .clone()
+                    .instantiate(type_env);
 
                 Node::Variable { 
                     name: value.clone(), 
                     token: token.clone(), 
-                    typ: typ.clone()
+                    typ
                 }
             },
 
             Token::Assigment { ref value, .. } => {
+                // The function is bound again, now that its body is checked
+                if let Some(i) = declared.iter().position(|(name, _)| name == value) {
+                    let (_, shadowed) = declared.remove(i);
+
+                    match shadowed {
+                        Some(scheme) => type_env.bindings.insert(value.clone(), scheme),
+
+                        None => type_env.bindings.remove(value)
+                    };
+                }
+
+                type_env.infer_missing(1);
+
                 if let Some(val) = type_env.stack.pop() {
                     Node::Assigment { 
                         name: value.clone(), 
@@ -74,7 +158,7 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
 
             Token::List { ref value, .. } if value.is_empty() =>
                 Node::Literal { 
-                    typ: var_type("a", None),
+                    typ: list_type(type_env.new_var("a")),
                     value: Literal::List(Vec::new()),
                     token: token.clone()
                 },
@@ -82,34 +166,136 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
             Token::List { ref value, .. } => {
                 let mut new_env = type_env.clone();
                 new_env.stack.clear();
+                new_env.inferred_args = None;
 
                 let ast = parse(value.clone(), &mut new_env)?;
                 
-                match typecheck_list(&new_env.stack) {
-                    Ok(typ) => 
+                match typecheck_list(&mut new_env) {
+                    Ok(typ) => {
+                        type_env.substitution = new_env.substitution;
+
                         Node::Literal { 
                             typ: list_type(typ), 
                             token: token.clone(),
                             value: Literal::List(ast)
-                        },
+                        }
+                    },
 
                     Err((expected, got)) => {
-                        return Err(Error::WrongTypeInList { token: token.clone(), expected, got });
+                        return Err(Error::WrongTypeInList { 
+                            token: token.clone(), 
+                            expected: new_env.resugar(&expected), 
+                            got: new_env.resugar(&got)
+                        });
                     },
                 }                
             },
 
-            Token::Function { sig: sig_src, body, .. } => {
-                let sig: TypedSignature = sig_src.into();
+            Token::Tuple { ref value, .. } => {
+                let mut new_env = type_env.clone();
+                new_env.stack.clear();
+                new_env.inferred_args = None;
+
+                let ast = parse(value.clone(), &mut new_env)?;
+
+                // Just like for lists, the first element is on top
+                let elems = new_env.apply_list(&new_env.stack)
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect();
+
+                type_env.substitution = new_env.substitution;
+
+                Node::Literal { 
+                    typ: tuple_type(elems), 
+                    token: token.clone(),
+                    value: Literal::Tuple(ast)
+                }
+            },
+
+            Token::Unpack { .. } => {
+                let elems = match type_env.stack.last().map(|typ| type_env.apply(typ)) {
+                    Some(Type::Kind(name, elems)) if name == TUPLE_TYPE_NAME => elems,
+
+                    other => return Err(Error::InvalidUnpack { 
+                        token: token.clone(), 
+                        got: other.map(|typ| type_env.resugar(&typ))
+                    })
+                };
+
+                // The first element ends up on top
+                Node::Call { 
+                    name: "unpack".to_string(), 
+                    token: token.clone(), 
+                    arguments: TypeList::from(vec![tuple_type(elems.vec().clone())]), 
+                    returns: TypeList::from(elems.iter().rev().cloned().collect::<Vec<Type>>()),
+                    constraints: TypeList::new()
+                }
+            },
+
+            Token::Function { sig: None, body, .. } => {
+                let mut new_env = type_env.clone();
+                new_env.stack.clear();
+                new_env.inferred_args = Some(TypeList::new());
+                new_env.function_returns = None;
+                new_env.wanted = Default::default();
+
+                let ast = parse(body, &mut new_env)?;
+
+                // Constraints which are not solved yet become part of the signature
+                let constraints = solve_constraints(&new_env, true)?;
+
+                // The variables stay as they are, the function is only generic
+                // once it is assigned
+                let sig = TypedSignature::new(
+                    new_env.apply_list(&new_env.inferred_args.clone().unwrap_or_default()), 
+                    new_env.apply_list(&new_env.stack)
+                )
+                .with_constraints(constraints);
+
+                type_env.substitution = new_env.substitution;
+
+                Node::Literal { 
+                    typ: sig.clone().into(),
+                    value: Literal::Function(sig, ast),
+                    token: token.clone()
+                }
+            },
+
+            Token::Function { sig: Some(sig_src), body, .. } => {
+                let invalid = |msg: String| Error::InvalidSignature { token: token.clone(), msg };
+
+                let sig = TypedSignature::build(sig_src, &type_env.aliases).map_err(invalid)?;
+                check_type_names(sig.arguments(), None, type_env).map_err(invalid)?;
+                check_type_names(sig.returns(), None, type_env).map_err(invalid)?;
+                check_constraints(sig.constraints(), type_env).map_err(invalid)?;
+
+                // The body is checked with variables which cannot be bound to anything
+                let (instance, fresh) = sig.instantiate(type_env);
 
                 let mut new_env = type_env.clone();
-                new_env.stack = sig.arguments().clone();
+                new_env.stack = instance.arguments().clone();
+                new_env.inferred_args = None;
+                new_env.function_returns = Some(instance.returns().clone());
+                new_env.given = instance.constraints().clone();
+                new_env.wanted = Default::default();
 
                 // Typecheck args
                 let ast = parse(body, &mut new_env)?;
 
                 // Typecheck return
-                typecheck_func_return(token, sig.returns().clone(), &mut new_env)?;
+                typecheck_func_return(token, &instance, &fresh, &mut new_env)?;
+
+                solve_constraints(&new_env, false)?;
+
+                // The body uses the variables of the signature again
+                let ast = ast
+                    .iter()
+                    .map(|node| node.substitute(&new_env.substitution).substitute(&fresh.inverse()))
+                    .collect();
+
+                type_env.substitution = new_env.substitution;
 
                 Node::Literal { 
                     typ: sig.clone().into(),
@@ -118,7 +304,151 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
                 }
             },
 
-            Token::Newline => unreachable!(),
+            Token::Record { name, params, fields, .. } => {
+                let invalid = |msg: String| Error::InvalidRecord { token: token.clone(), msg };
+
+                let params = type_params(params).map_err(invalid)?;
+                let mut typed_fields: Vec<(String, Type)> = Vec::new();
+
+                for (field, elem) in fields {
+                    if typed_fields.iter().any(|(other, _)| *other == field) {
+                        return Err(invalid(format!("The field {} is declared twice", (&field).fg(Color::Cyan))))
+                    }
+
+                    if !uses_only(&elem, &params) {
+                        return Err(invalid(format!(
+                            "The field {} may only use the type variables of the record", 
+                            (&field).fg(Color::Cyan)
+                        )))
+                    }
+
+                    let typ = build_type(elem, &type_env.aliases).map_err(invalid)?;
+                    check_type_names(&TypeList::from(vec![typ.clone()]), Some(&name), type_env).map_err(invalid)?;
+
+                    typed_fields.push((field, typ));
+                }
+
+                Node::Record { 
+                    record: Record::new(name, params, typed_fields), 
+                    token: token.clone() 
+                }
+            },
+
+            Token::Union { name, params, variants, .. } => {
+                let invalid = |msg: String| Error::InvalidUnion { token: token.clone(), msg };
+
+                if variants.is_empty() {
+                    return Err(invalid(format!("The union {} needs at least one variant", (&name).fg(Color::Cyan))))
+                }
+
+                let params = type_params(params).map_err(invalid)?;
+                let mut typed_variants: Vec<(String, Vec<Type>)> = Vec::new();
+
+                for variant in variants {
+                    let (variant, payload) = match variant {
+                        SignatureElement::Kind(variant, payload) => (variant, payload),
+
+                        _ => return Err(invalid("Variants need a name, e.g. circle[num]".to_string()))
+                    };
+
+                    if typed_variants.iter().any(|(other, _)| *other == variant) {
+                        return Err(invalid(format!("The variant {} is declared twice", (&variant).fg(Color::Cyan))))
+                    }
+
+                    if !payload.iter().all(|elem| uses_only(elem, &params)) {
+                        return Err(invalid(format!(
+                            "The payload of {} may only use the type variables of the union", 
+                            (&variant).fg(Color::Cyan)
+                        )))
+                    }
+
+                    let payload = payload
+                        .into_iter()
+                        .map(|elem| build_type(elem, &type_env.aliases))
+                        .collect::<Result<Vec<Type>, String>>()
+                        .map_err(invalid)?;
+
+                    check_type_names(&TypeList::from(payload.clone()), Some(&name), type_env).map_err(invalid)?;
+
+                    typed_variants.push((variant, payload));
+                }
+
+                Node::Union { 
+                    union: Union::new(name, params, typed_variants), 
+                    token: token.clone() 
+                }
+            },
+
+            Token::TypeAlias { name, params, typ, .. } => {
+                let invalid = |msg: String| Error::InvalidTypeAlias { token: token.clone(), msg };
+
+                let params = type_params(params).map_err(invalid)?;
+
+                if !uses_only(&typ, &params) {
+                    return Err(invalid(format!(
+                        "The type {} may only use its own type variables", 
+                        (&name).fg(Color::Cyan)
+                    )))
+                }
+
+                let typ = build_type(typ, &type_env.aliases).map_err(invalid)?;
+                check_type_names(&TypeList::from(vec![typ.clone()]), None, type_env).map_err(invalid)?;
+
+                Node::TypeAlias { 
+                    alias: Alias::new(name, params, typ), 
+                    token: token.clone() 
+                }
+            },
+
+            Token::Class { name, params, methods, .. } => {
+                let invalid = |msg: String| Error::InvalidClass { token: token.clone(), msg };
+
+                let param = match &type_params(params).map_err(invalid)?[..] {
+                    [param] => param.clone(),
+
+                    _ => return Err(invalid("A class has exactly one type variable, e.g. Show['a]".to_string()))
+                };
+
+                let mut typed_methods: Vec<(String, TypedSignature)> = Vec::new();
+
+                for (method, sig) in methods {
+                    if typed_methods.iter().any(|(other, _)| *other == method) {
+                        return Err(invalid(format!("The method {} is declared twice", (&method).fg(Color::Cyan))))
+                    }
+
+                    let sig = TypedSignature::build(sig, &type_env.aliases).map_err(invalid)?;
+                    check_type_names(sig.arguments(), None, type_env).map_err(invalid)?;
+                    check_type_names(sig.returns(), None, type_env).map_err(invalid)?;
+
+                    if !sig.constraints().is_empty() {
+                        return Err(invalid(format!("The method {} cannot have constraints", (&method).fg(Color::Cyan))))
+                    }
+
+                    if !sig.arguments().occurs(&param) && !sig.returns().occurs(&param) {
+                        return Err(invalid(format!(
+                            "The signature of {} has to use {}", 
+                            (&method).fg(Color::Cyan), 
+                            format!("'{param}").fg(Color::Cyan)
+                        )))
+                    }
+
+                    typed_methods.push((method, sig));
+                }
+
+                Node::Class { 
+                    class: Class::new(name, param, typed_methods), 
+                    token: token.clone() 
+                }
+            },
+
+            Token::Instance { class, params, methods, .. } => parse_instance(token, class, params, methods, type_env)?,
+
+            Token::Match { arms, .. } => parse_match(token, arms, type_env)?,
+
+            Token::Try { .. } => parse_try(token, type_env)?,
+
+            // Both are removed by the lexer
+            Token::Newline | Token::Comment { .. } => unreachable!(),
         };
 
         node.apply(type_env)?;
@@ -128,43 +458,644 @@ pub fn parse(mut tokens: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>
     Ok(typed_stack)
 }
 
-fn typecheck_list(list: &TypeList) -> Result<Type, (Type, Type)> {
+/// The tokens are reversed, so the assignment comes after the function
+fn is_named_function(token: &Token, next: Option<&Token>) -> bool {
+    matches!((token, next), (Token::Function { sig: Some(_), .. }, Some(Token::Assigment { .. })))
+}
+
+/// Binds the function and all named functions after it with their signatures, so that 
+/// they can call themselves and each other. Functions whose signatures are invalid 
+/// or use aliases which are not declared yet are left out, they are checked later.
+/// The function itself shadows an existing binding until its assignment, the ones 
+/// after it don't, as the code in between still uses the existing binding
+fn declare_named_functions(token: &Token, tokens: &[Token], type_env: &mut TypeEnv, declared: &mut Vec<(String, Option<Scheme>)>) {
+    let current = [tokens.last().unwrap().clone(), token.clone()];
+
+    // In the order they are defined
+    let pairs = [&current[..]]
+        .into_iter()
+        .chain(tokens.windows(2).rev())
+        .filter(|pair| is_named_function(&pair[1], Some(&pair[0])));
+
+    for (i, pair) in pairs.enumerate() {
+        let (Token::Assigment { value, .. }, Token::Function { sig: Some(sig), .. }) = (&pair[0], &pair[1]) else {
+            unreachable!()
+        };
+
+        if declared.iter().any(|(name, _)| name == value) || (i > 0 && type_env.bindings.contains_key(value)) {
+            continue
+        }
+
+        if let Ok(sig) = TypedSignature::build(sig.clone(), &type_env.aliases) {
+            let shadowed = type_env.bindings.insert(value.clone(), Scheme::closed(sig.into()));
+            declared.push((value.clone(), shadowed));
+        }
+    }
+}
+
+fn parse_interpolated_code(token: &Token, code: Vec<Token>, type_env: &mut TypeEnv) -> Result<Vec<Node>, Error> {
+    let mut new_env = type_env.clone();
+    new_env.stack.clear();
+    new_env.inferred_args = None;
+
+    let mut ast = parse(code, &mut new_env)?;
+    let results = new_env.apply_list(&new_env.stack);
+    type_env.substitution = new_env.substitution;
+
+    // Everything that is not a str yet has to be converted to one
+    let conversion = match &results.vec()[..] {
+        [Type::Kind(name, _)] if name == QUOTE_TYPE_NAME => None,
+
+        [Type::Kind(name, _)] if name == NUMBER_TYPE_NAME => Some("num2str"),
+
+        [Type::Kind(name, _)] if name == INTEGER_TYPE_NAME => Some("int2str"),
+
+        [Type::Kind(name, _)] if name == BOOL_TYPE_NAME => Some("bool2str"),
+
+        _ => return Err(Error::InvalidInterpolation { token: token.clone(), got: type_env.resugar_list(&results) })
+    };
+
+    if let Some(name) = conversion {
+        ast.push(Node::Call { 
+            name: name.to_string(), 
+            token: token.clone(), 
+            arguments: results, 
+            returns: TypeList::from(vec![quote_type()]),
+            constraints: TypeList::new()
+        });
+    }
+
+    Ok(ast)
+}
+
+fn parse_match(token: &Token, arms: Vec<(String, Vec<Token>)>, type_env: &mut TypeEnv) -> Result<Node, Error> {
+    let invalid = |msg: String| Error::InvalidMatch { token: token.clone(), msg };
+
+    let union = match arms.first() {
+        Some((variant, _)) => type_env.union_of_variant(variant)
+            .ok_or_else(|| invalid(format!("{} is not a variant of any union", variant.fg(Color::Cyan))))?
+            .clone(),
+
+        None => return Err(invalid("A match needs at least one arm".to_string()))
+    };
+
+    // A generic union gets fresh type variables for every match
+    let instance = Substitution::fresh(&union.params, type_env);
+
+    let mut typed_arms: Vec<Option<(Vec<Node>, TypedSignature)>> = vec![None; union.variants.len()];
+
+    for (variant, body) in arms {
+        let tag = union.tag(&variant)
+            .ok_or_else(|| invalid(format!(
+                "{} is not a variant of {}", 
+                (&variant).fg(Color::Cyan), 
+                (&union.name).fg(Color::Cyan)
+            )))?;
+
+        if typed_arms[tag].is_some() {
+            return Err(invalid(format!("There are two arms for {}", variant.fg(Color::Cyan))))
+        }
+
+        // Every arm starts with the payload on the stack
+        let payload = TypeList::from(union.payload(tag)
+            .iter()
+            .rev()
+            .map(|typ| typ.substitute(&instance))
+            .collect::<Vec<Type>>());
+
+        typed_arms[tag] = Some(parse_branch(body, payload, type_env)?);
+    }
+
+    let missing: Vec<String> = union.variants
+        .iter()
+        .zip(&typed_arms)
+        .filter(|(_, arm)| arm.is_none())
+        .map(|((variant, _), _)| variant.fg(Color::Cyan).to_string())
+        .collect();
+
+    match &missing[..] {
+        [] => (),
+
+        [variant] => return Err(invalid(format!("The arm for {variant} is missing"))),
+
+        _ => return Err(invalid(format!("The arms for {} are missing", missing.join(", "))))
+    }
+
+    // Like the branches of an if, the arms may take a different number of the values they leave as they are
+    let len = typed_arms
+        .iter()
+        .flatten()
+        .map(|(_, effect)| effect.arguments().len())
+        .max()
+        .unwrap_or(0);
+
+    let typed_arms: Vec<(Vec<Node>, TypedSignature)> = typed_arms
+        .into_iter()
+        .flatten()
+        .map(|(ast, effect)| (ast, widen_effect(&effect, len, type_env)))
+        .collect();
+
+    // All arms have to agree with the first one
+    let (_, effect) = typed_arms.first().unwrap().clone();
+
+    for (tag, (_, other)) in typed_arms.iter().enumerate().skip(1) {
+        let mut arm_env = type_env.clone();
+        let compatible = effect.returns().len() == other.returns().len()
+            && arm_env.unify_lists(effect.arguments(), other.arguments()).is_ok()
+            && arm_env.unify_lists(effect.returns(), other.returns()).is_ok();
+
+        if !compatible {
+            return Err(Error::IncompatibleMatchArms { 
+                token: token.clone(), 
+                variant: union.variants[tag].0.clone(), 
+                expected: show_effect(type_env, &effect), 
+                got: show_effect(type_env, other)
+            })
+        }
+
+        type_env.substitution = arm_env.substitution;
+    }
+
+    let mut arguments = effect.arguments().clone();
+    arguments.push(union.typ().substitute(&instance));
+
+    Ok(Node::Match { 
+        union, 
+        arms: typed_arms.into_iter().map(|(ast, _)| ast).collect(), 
+        token: token.clone(), 
+        arguments, 
+        returns: effect.returns().clone()
+    })
+}
+
+/// Infers the stack effect of a match arm on its own, 
+/// starting with the given values on the stack
+fn parse_branch(body: Vec<Token>, stack: TypeList, type_env: &mut TypeEnv) -> Result<(Vec<Node>, TypedSignature), Error> {
+    let mut branch_env = type_env.clone();
+    branch_env.stack = stack;
+    branch_env.inferred_args = Some(TypeList::new());
+
+    let ast = parse(body, &mut branch_env)?;
+    type_env.substitution = branch_env.substitution;
+
+    let effect = TypedSignature::new(branch_env.inferred_args.unwrap_or_default(), branch_env.stack);
+
+    Ok((ast, effect))
+}
+
+/// Adds values below the effect, which it leaves as they are, until it takes n arguments
+fn widen_effect(effect: &TypedSignature, n: usize, type_env: &TypeEnv) -> TypedSignature {
+    let below: Vec<Type> = (effect.arguments().len()..n)
+        .map(|_| type_env.new_var("t"))
+        .collect();
+
+    let widen = |list: &TypeList| {
+        let mut widened = TypeList::from(below.clone());
+        widened.extend(list.clone());
+        widened
+    };
+
+    TypedSignature::new(widen(effect.arguments()), widen(effect.returns()))
+}
+
+/// The names of the type variables a generic type declaration takes
+fn type_params(params: Vec<SignatureElement>) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for param in params {
+        match param {
+            SignatureElement::Variable(name) if names.contains(&name) => 
+                return Err(format!("The type variable {} is declared twice", format!("'{name}").fg(Color::Cyan))),
+
+            SignatureElement::Variable(name) => names.push(name),
+
+            _ => return Err("Only type variables can be parameters, e.g. pair['a]['b]".to_string())
+        }
+    }
+
+    Ok(names)
+}
+
+/// Whether all type variables of the element are parameters
+fn uses_only(elem: &SignatureElement, params: &[String]) -> bool {
+    match elem {
+        SignatureElement::Kind(_, inner) => inner.iter().all(|elem| uses_only(elem, params)),
+
+        SignatureElement::Variable(name) => params.contains(name),
+
+        SignatureElement::Row(_) => false
+    }
+}
+
+fn parse_instance(
+    token: &Token, 
+    class: String, 
+    params: Vec<SignatureElement>, 
+    methods: Vec<(String, Vec<Token>)>, 
+    type_env: &mut TypeEnv
+) -> Result<Node, Error> {
+    let invalid = |msg: String| Error::InvalidInstance { token: token.clone(), msg };
+
+    let class = type_env.classes
+        .get(&class)
+        .ok_or_else(|| invalid(format!("The class {} does not exist", (&class).fg(Color::Cyan))))?
+        .clone();
+
+    let typ = match &params[..] {
+        [elem] if uses_only(elem, &[]) => build_type(elem.clone(), &type_env.aliases).map_err(invalid)?,
+
+        _ => return Err(invalid("An instance is for one type without type variables, e.g. Show[num]".to_string()))
+    };
+
+    check_type_names(&TypeList::from(vec![typ.clone()]), None, type_env).map_err(invalid)?;
+
+    let mut typed_methods: Vec<(String, Vec<Node>)> = Vec::new();
+
+    for (method, body) in methods {
+        let sig = class.method_signature(&method, &typ)
+            .ok_or_else(|| invalid(format!(
+                "{} is not a method of {}", 
+                (&method).fg(Color::Cyan), 
+                (&class.name).fg(Color::Cyan)
+            )))?;
+
+        if typed_methods.iter().any(|(other, _)| *other == method) {
+            return Err(invalid(format!("The method {} is defined twice", (&method).fg(Color::Cyan))))
+        }
+
+        let (sig, fresh) = sig.instantiate(type_env);
+
+        let mut method_env = type_env.clone();
+        method_env.stack = sig.arguments().clone();
+        method_env.inferred_args = None;
+        method_env.given = TypeList::new();
+        method_env.wanted = Default::default();
+
+        // The body is inlined, so there is no function to return from
+        method_env.function_returns = None;
+
+        let ast = parse(body, &mut method_env)?;
+        typecheck_func_return(token, &sig, &fresh, &mut method_env)?;
+        solve_constraints(&method_env, false)?;
+
+        // The body does not use anything from outside, so it can be finished right away
+        let ast = ast
+            .iter()
+            .map(|node| node.substitute(&method_env.substitution).substitute(&fresh.inverse()))
+            .collect();
+
+        typed_methods.push((method, ast));
+    }
+
+    let missing: Vec<String> = class.methods
+        .iter()
+        .filter(|(method, _)| !typed_methods.iter().any(|(other, _)| other == method))
+        .map(|(method, _)| method.fg(Color::Cyan).to_string())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(invalid(format!("The methods {} are missing", missing.join(", "))))
+    }
+
+    Ok(Node::Instance { 
+        instance: Instance::new(class.name, typ, typed_methods), 
+        token: token.clone() 
+    })
+}
+
+/// Solves the constraints wanted since the start of the function. The ones on type variables
+/// have to be given by its signature, unless it is inferred: then they are returned
+pub fn solve_constraints(type_env: &TypeEnv, infer: bool) -> Result<TypeList, Error> {
+    let wanted: Vec<(Type, Token)> = type_env.wanted
+        .lock()
+        .unwrap()
+        .drain(..)
+        .collect();
+
+    let given = type_env.apply_list(&type_env.given);
+    let mut inferred = TypeList::new();
+
+    for (constraint, token) in wanted {
+        let constraint = type_env.apply(&constraint);
+
+        let is_variable = match &constraint {
+            Type::Kind(_, args) => matches!(args.first(), Some(Type::Variable(..))),
+
+            Type::Variable(..) => false
+        };
+
+        if !is_variable {
+            if type_env.instance(&constraint).is_none() {
+                return Err(Error::MissingInstance { token, constraint: type_env.resugar(&constraint) })
+            }
+        }
+        else if infer {
+            if !inferred.contains(&constraint) && !given.contains(&constraint) {
+                inferred.push(constraint);
+            }
+        }
+        else if !given.contains(&constraint) {
+            return Err(Error::UnsolvedConstraint { token, constraint })
+        }
+    }
+
+    Ok(inferred)
+}
+
+/// Constraints in signatures look like `Num['a]`
+/// Makes sure every type is a builtin, a record, a union or an alias, e.g. not `foo` 
+/// in `( foo -- )`. The type being declared may use itself
+fn check_type_names(types: &TypeList, declared: Option<&str>, type_env: &TypeEnv) -> Result<(), String> {
+    for typ in types.iter() {
+        let Type::Kind(name, inner) = typ else {
+            continue
+        };
+
+        // The arguments and returns of a function type, the constraints after them are classes
+        if name == FUNC_TYPE_NAME {
+            for side in inner.iter().take(2) {
+                if let Type::Kind(_, side) = side {
+                    check_type_names(side, declared, type_env)?;
+                }
+            }
+
+            continue
+        }
+
+        if !type_env.type_exists(name) && Some(name.as_str()) != declared {
+            return Err(format!("The type {} does not exist", name.fg(Color::Cyan)))
+        }
+
+        check_type_names(inner, declared, type_env)?;
+    }
+
+    Ok(())
+}
+
+fn check_constraints(constraints: &TypeList, type_env: &TypeEnv) -> Result<(), String> {
+    for constraint in constraints.iter() {
+        match constraint {
+            Type::Kind(class, args) if !type_env.classes.contains_key(class) || args.len() != 1 => 
+                return Err(format!("{} is not a class with one type variable", class.fg(Color::Cyan))),
+
+            Type::Kind(_, args) if !matches!(args.first(), Some(Type::Variable(..))) => 
+                return Err(format!("The constraint {} has to be on a type variable", constraint.fg(Color::Cyan))),
+
+            _ => ()
+        }
+    }
+
+    Ok(())
+}
+
+/// The failure is returned as the only value, so `try` is restricted to functions
+/// returning exactly one option or result, e.g. not `( int -- option[int] int )`
+fn parse_try(token: &Token, type_env: &mut TypeEnv) -> Result<Node, Error> {
+    let invalid = |msg: String| Error::InvalidTry { token: token.clone(), msg };
+
+    let function_returns = type_env.function_returns
+        .clone()
+        .ok_or_else(|| invalid("try can only be used in functions with a signature".to_string()))?;
+
+    let (name, args) = match type_env.stack.last().map(|typ| type_env.apply(typ)) {
+        Some(Type::Kind(name, args)) if name == OPTION_TYPE_NAME || name == RESULT_TYPE_NAME => (name, args),
+
+        Some(other) => return Err(invalid(format!(
+            "try expects an option or a result, got {}", 
+            type_env.resugar(&other).fg(Color::Red)
+        ))),
+
+        None => return Err(invalid("try expects an option or a result, but the stack is empty".to_string()))
+    };
+
+    let failure = match name.as_str() {
+        OPTION_TYPE_NAME => "none".to_string(),
+
+        _ => format!("err[{}]", type_env.resugar(&args[1]))
+    };
+
+    // The failure is returned as it is, so the function has to return the same kind of failure
+    let returned = type_env.apply_list(&function_returns);
+
+    if returned.row().is_some() || returned.len() != 1 {
+        return Err(invalid(format!(
+            "try returns {} early as the only value, so the function has to return a single {}, not {}",
+            failure.fg(Color::Cyan),
+            name.fg(Color::Cyan),
+            type_env.resugar_list(&function_returns).fg(Color::Red)
+        )))
+    }
+
+    let compatible = match &returned[0] {
+        Type::Kind(returned_name, returned_args) if *returned_name == name => 
+            name == OPTION_TYPE_NAME || type_env.unify(&args[1], &returned_args[1]).is_ok(),
+
+        _ => false
+    };
+
+    if !compatible {
+        return Err(invalid(format!(
+            "try returns {} early, but the function returns {}",
+            failure.fg(Color::Cyan),
+            type_env.resugar_list(&function_returns).fg(Color::Red)
+        )))
+    }
+
+    Ok(Node::Try { 
+        token: token.clone(), 
+        arguments: TypeList::from(vec![Type::Kind(name, args.clone())]), 
+        returns: TypeList::from(vec![args[0].clone()])
+    })
+}
+
+fn resugar_signature(type_env: &TypeEnv, sig: &TypedSignature) -> TypedSignature {
+    TypedSignature::new(
+        type_env.resugar_list(sig.arguments()), 
+        type_env.resugar_list(sig.returns())
+    )
+}
+
+/// The stack effect of a branch or loop body with everything known about it, for errors
+fn show_effect(type_env: &TypeEnv, effect: &TypedSignature) -> TypedSignature {
+    resugar_signature(type_env, &effect.substitute(&type_env.substitution).generalize())
+}
+
+/// All elements have the same type, the first one is on top
+fn typecheck_list(env: &mut TypeEnv) -> Result<Type, (Type, Type)> {
     // benjamin verifiziert
-    match &list.vec()[..] {
-        [] => Ok(var_type("a", None)),
+    let list = env.stack.clone();
 
-        [one] => Ok(one.clone()),
+    match &list.vec()[..] {
+        [] => Ok(env.new_var("a")),
 
-        [first, ..]  => {
-            for elem in list.vec() {
-                if elem != first {
-                    return Err((first.clone(), elem.clone()))
+        [.., first]  => {
+            for elem in list.iter().rev() {
+                if env.unify(first, elem).is_err() {
+                    return Err((env.apply(first), env.apply(elem)))
                 }
             }
 
-            Ok(first.clone())
+            Ok(env.apply(first))
         }
     }
 }
 
-fn typecheck_func_return(token: &Token, results: TypeList, new_env: &mut TypeEnv) -> Result<(), Error> {
-    if new_env.stack.len() != results.len() {
-        return Err(Error::IncompatibleFunctionReturn {  token: token.clone(), expected: results, got: new_env.clone().stack });
+/// The body has to leave the returns of the signature on the stack. Its type
+/// variables are rigid, so `( 'a -- num ) { add 1 }` is rejected
+fn typecheck_func_return(token: &Token, sig: &TypedSignature, fresh: &Substitution, new_env: &mut TypeEnv) -> Result<(), Error> {
+    // Errors show the variables as they were written
+    let original = fresh.inverse();
+    let results = sig.returns();
+
+    let incompatible = |new_env: &TypeEnv| Error::IncompatibleFunctionReturn { 
+        token: token.clone(), 
+        expected: new_env.resugar_list(&results.substitute(&original)), 
+        got: new_env.resugar_list(&new_env.apply_list(&new_env.stack).substitute(&original))
+    };
+
+    if !is_rigid(sig, new_env) {
+        return Err(Error::InvalidSignature { 
+            token: token.clone(), 
+            msg: "The function is less generic than its signature".to_string() 
+        })
+    }
+
+    let stack = new_env.apply_list(&new_env.stack);
+
+    if stack.len() != results.len() || stack.row().is_some() != results.row().is_some() {
+        return Err(incompatible(new_env));
     }
 
     let env_clone = new_env.clone();
-    let res = results.vec();
 
-    for i in (0..res.len()).rev() {
-        let stack_args = &new_env.stack.pop().unwrap().refresh_vars(new_env);
+    new_env.unify_lists(results, &stack)
+        .map_err(|msg| Error::UnificationError { token: token.clone(), msg })?;
 
-        res[i].unify(stack_args)
-            .map_err(|msg| Error::UnificationError { token: token.clone(), msg })?;
+    if !is_rigid(sig, new_env) {
+        return Err(incompatible(&env_clone));
     }
 
-    if results.has_bound_vars() || env_clone.stack.has_bound_vars() {
-        return Err(Error::IncompatibleFunctionReturn { token: token.clone(), expected: results, got: new_env.clone().stack });
+    Ok(())
+}
+
+/// Whether the variables of the signature still stand for any type, 
+/// and all of them for different ones
+fn is_rigid(sig: &TypedSignature, env: &TypeEnv) -> bool {
+    let mut seen = Vec::new();
+
+    for name in sig.variables() {
+        let bound = if name.starts_with("..") {
+            let list = env.apply_list(&TypeList::new().with_row(RowVar(name)));
+
+            match list.row() {
+                Some(row) if list.is_empty() => Some(row.name().clone()),
+
+                _ => None
+            }
+        }
+        else {
+            match env.apply(&var_type(&name)) {
+                Type::Variable(var) => Some(var),
+
+                Type::Kind(..) => None
+            }
+        };
+
+        match bound {
+            Some(var) if !seen.contains(&var) => seen.push(var),
+
+            _ => return false
+        }
     }
 
-    Ok(())
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use cranelift_jit::JITModule;
+
+    use crate::{lexer::lex, source::add_source, error::Error, stdlib::create_stdlib};
+
+    use super::parse_program;
+
+    fn parse_source(src: &str) -> Result<(), Error> {
+        let tokens = lex(add_source("test", src.to_string()))?;
+
+        parse_program(tokens, &mut create_stdlib::<JITModule>().type_env()).map(|_| ())
+    }
+
+    #[test]
+    fn recursive_function_calls_itself() {
+        assert!(parse_source("f: ( int -- int ) { f }\ng: ( num -- num ) { h h: ( num -- num ) { h } }").is_ok());
+    }
+
+    #[test]
+    fn shadowing_function_calls_itself() {
+        // The calls in the bodies are checked against the new functions, not the 
+        // existing bindings, so only the assignments fail
+        let shadowing = [
+            "f: 1\nf: ( int -- int ) { f }",
+            "g: ( num -- num ) { }\nh: ( int -- int ) { g g: ( int -- int ) { g } }"
+        ];
+
+        for src in shadowing {
+            assert!(matches!(parse_source(src), Err(Error::Reassigment { .. })), "{src}");
+        }
+    }
+
+    #[test]
+    fn missing_match_arms() {
+        let missing = |src: &str| match parse_source(src) {
+            Err(Error::InvalidMatch { msg, .. }) => msg,
+
+            _ => panic!("{src} is not an invalid match")
+        };
+
+        assert!(missing("union opt [sm[num] nn]\nmatch [sm { }] nn").starts_with("The arm for"));
+        assert!(missing("union abc [a b c]\nmatch [a { }] b").starts_with("The arms for"));
+    }
+
+    #[test]
+    fn try_returns_the_failure_only() {
+        let invalid = |src: &str| match parse_source(src) {
+            Err(Error::InvalidTry { msg, .. }) => msg,
+
+            _ => panic!("{src} is not an invalid try")
+        };
+
+        assert!(parse_source("f: ( option[int] -- option[int] ) { some iadd 1i try }").is_ok());
+
+        assert!(invalid("f: ( option[int] -- option[int] int ) { 1i some try }").contains("single"));
+        assert!(invalid("f: ( option[int] -- int option[int] ) { some try }").contains("single"));
+        assert!(invalid("f: ( option[int] -- result[int][str] ) { ok try }").contains("but the function returns"));
+    }
+
+    #[test]
+    fn unknown_type_names() {
+        let invalid = |src: &str| match parse_source(src) {
+            Err(
+                Error::InvalidSignature { msg, .. } | Error::InvalidRecord { msg, .. } | 
+                Error::InvalidUnion { msg, .. } | Error::InvalidTypeAlias { msg, .. }
+            ) => msg,
+
+            _ => panic!("{src} does not use an unknown type")
+        };
+
+        assert!(invalid("x: ( foo -- ) { drop }").contains("does not exist"));
+        assert!(invalid("x: ( -- fun[arg[foo]][ret[]] ) { { drop } }").contains("does not exist"));
+        assert!(invalid("record pt [x: nosuchtype]").contains("does not exist"));
+        assert!(invalid("type pt nosuchtype").contains("does not exist"));
+        assert!(invalid("type pts list[nosuchtype]").contains("does not exist"));
+        assert!(invalid("union sh [circle[nosuchtype] empty]").contains("does not exist"));
+
+        assert!(parse_source("record pt [x: num next: list[pt]]
+x: ( pt -- ) { drop }").is_ok());
+    }
+
+    #[test]
+    fn match_arms_take_different_values() {
+        assert!(parse_source("union opt [sm[num] nn]\nmatch [sm { fadd } nn { }] sm 1 5").is_ok());
+        assert!(parse_source("union opt [sm[num] nn]\nmatch [sm { fadd } nn { drop }] nn 5").is_err());
+    }
 }
@@ -1,6 +1,8 @@
+use ariadne::{Color, Fmt};
+
 use crate::{error::Error, lexer::token::Token};
 
-use super::{type_env::TypeEnv, typelist::TypeList, types::typ::Type, signature_parser::TypedSignature};
+use super::{type_env::TypeEnv, typelist::TypeList, types::{typ::Type, record::Record, substitution::Substitution, scheme::Scheme, tagged_union::Union, alias::Alias, class::{Class, Instance}}, signature_parser::TypedSignature};
 
 #[derive(Clone, Debug)]
 pub enum Node {
@@ -12,21 +14,74 @@ pub enum Node {
 
     Variable {
         name: String,
+        #[allow(dead_code)]
         token: Token,
         typ: Type
     },
 
+    /// The constraints of the called word, e.g. `Num[int]` for `add 1i 2i`
     Call {
         name: String,
         token: Token,
         arguments: TypeList,
-        returns: TypeList
+        returns: TypeList,
+        constraints: TypeList
     },
 
     Literal {
         typ: Type,
         value: Literal,
+        #[allow(dead_code)]
+        token: Token
+    },
+
+    /// Declares the record type and its words
+    Record {
+        record: Record,
         token: Token
+    },
+
+    /// Declares the union type and its constructors
+    Union {
+        union: Union,
+        token: Token
+    },
+
+    /// Declares the alias, it is expanded by all later signatures
+    TypeAlias {
+        alias: Alias,
+        token: Token
+    },
+
+    /// Declares the class and its methods
+    Class {
+        class: Class,
+        token: Token
+    },
+
+    /// Makes the methods of the class available for the type
+    Instance {
+        instance: Instance,
+        token: Token
+    },
+
+    /// Takes a value of the union, the arms are ordered by tag.
+    /// The union is the last argument
+    Match {
+        union: Union,
+        arms: Vec<Vec<Node>>,
+        token: Token,
+        arguments: TypeList,
+        returns: TypeList
+    },
+
+    /// Takes an option or a result and pushes the value on success, 
+    /// a failure is returned from the function right away. So the function 
+    /// has to return nothing but the same kind of option or result
+    Try {
+        token: Token,
+        arguments: TypeList,
+        returns: TypeList
     }
 }
 
@@ -34,22 +89,40 @@ pub enum Node {
 pub enum Literal {
     Quote(String),
 
+    Interpolation(Vec<InterpolationPart>),
+
     Number(f64),
 
+    Integer(i64),
+
+    Bool(bool),
+
     List(Vec<Node>),
 
+    Tuple(Vec<Node>),
+
     Function(TypedSignature, Vec<Node>)
 }
 
+#[derive(Clone, Debug)]
+pub enum InterpolationPart {
+    Text(String),
+
+    /// Code which results in exactly one str
+    Code(Vec<Node>)
+}
+
 impl Node {
-    pub fn apply(&self, env: &mut TypeEnv) -> Result<(), Error> {
+    pub fn apply(&mut self, env: &mut TypeEnv) -> Result<(), Error> {
         match self {
             Node::Assigment { name, typ, token, .. } => {
                 if env.bindings.contains_key(name){
                     return Err(Error::Reassigment { token: token.clone() });
                 }
 
-                env.bindings.insert(name.clone(), typ.clone());
+                // Let-polymorphism: every use of the binding may have other types
+                let scheme = Scheme::generalize(typ, env);
+                env.bindings.insert(name.clone(), scheme);
                 Ok(())
             },
 
@@ -58,43 +131,135 @@ impl Node {
                 Ok(())
             },
 
-            Node::Call { name, arguments, returns, token, .. } => {            
-                let arg_len = arguments.len();
-                let stack_len = env.stack.len();
-            
-                if arg_len > stack_len {
-                    return Err(Error::WrongArguments { 
-                        fname: name.clone(), token: token.clone(), expected: arguments.clone(), got: env.stack.clone() 
-                    })
-                }
+            // The called word was instantiated when it was looked up
+            Node::Call { name, arguments, returns, constraints, token } => {            
+                env.infer_missing(arguments.len());
+                env.infer_missing(effect_depth(env, arguments));
 
-                let args = arguments.vec();
-            
-                let mut tenv = env.clone();
-            
-                for i in (0..arg_len).rev() {
-                    let stack_args = &tenv.stack.pop().unwrap().refresh_vars(&mut tenv);
-            
-                    args[i].unify(stack_args)
-                        .map_err(|_| Error::WrongArguments { 
-                            fname: name.clone(), token: token.clone(), expected: arguments.clone(), got: env.stack.clone() 
-                        })?;
-                }
-            
-                tenv.stack.extend(returns.concretize());
-            
-                arguments.clear_vars();
-                returns.clear_vars();
-            
-                *env = tenv;
+                let (instance_args, instance_returns) = apply_effect(env, arguments, returns)
+                    .map_err(|got| Error::WrongArguments { 
+                        fname: name.clone(), 
+                        token: token.clone(), 
+                        expected: expected_arguments(env, arguments), 
+                        got: env.resugar_list(&got)
+                    })?;
+
+                // Remember the types this function was actually called with,
+                // without the rest of the stack
+                *arguments = instance_args;
+                *returns = instance_returns;
+
+                // They are solved at the end of the function, when all types are known
+                env.want(constraints, token);
             
                 Ok(())
             },
 
+            Node::Match { arguments, returns, token, .. } => {
+                env.infer_missing(arguments.len());
+
+                // The arms were only typechecked for this match, so there is nothing to instantiate
+                let (concrete_args, concrete_returns) = apply_effect(env, arguments, returns)
+                    .map_err(|got| Error::WrongArguments { 
+                        fname: "match".to_string(), 
+                        token: token.clone(), 
+                        expected: expected_arguments(env, arguments), 
+                        got: env.resugar_list(&got)
+                    })?;
+
+                *arguments = concrete_args;
+                *returns = concrete_returns;
+
+                Ok(())
+            },
+
+            Node::Try { arguments, returns, token } => {
+                let (concrete_args, concrete_returns) = apply_effect(env, arguments, returns)
+                    .map_err(|got| Error::WrongArguments { 
+                        fname: "try".to_string(), 
+                        token: token.clone(), 
+                        expected: expected_arguments(env, arguments), 
+                        got: env.resugar_list(&got)
+                    })?;
+
+                *arguments = concrete_args;
+                *returns = concrete_returns;
+
+                Ok(())
+            },
+
             Node::Literal { typ, .. } => {
                 env.stack.push(typ.clone());
                 Ok(())
             },
+
+            Node::Record { record, token } => {
+                let invalid = |msg: String| Error::InvalidRecord { token: token.clone(), msg };
+
+                if env.type_exists(&record.name) {
+                    return Err(invalid(format!("The type {} already exists", (&record.name).fg(Color::Cyan))))
+                }
+
+                let words = record.words()
+                    .into_iter()
+                    .map(|(name, _, sig)| (name, sig))
+                    .collect();
+
+                declare_words(env, words, invalid)?;
+
+                env.records.insert(record.name.clone(), record.clone());
+                Ok(())
+            },
+
+            Node::Union { union, token } => {
+                let invalid = |msg: String| Error::InvalidUnion { token: token.clone(), msg };
+
+                if env.type_exists(&union.name) {
+                    return Err(invalid(format!("The type {} already exists", (&union.name).fg(Color::Cyan))))
+                }
+
+                declare_words(env, union.words(), invalid)?;
+
+                env.unions.insert(union.name.clone(), union.clone());
+                Ok(())
+            },
+
+            Node::Class { class, token } => {
+                let invalid = |msg: String| Error::InvalidClass { token: token.clone(), msg };
+
+                if env.classes.contains_key(&class.name) {
+                    return Err(invalid(format!("The class {} already exists", (&class.name).fg(Color::Cyan))))
+                }
+
+                declare_words(env, class.words(), invalid)?;
+
+                env.classes.insert(class.name.clone(), class.clone());
+                Ok(())
+            },
+
+            Node::Instance { instance, token } => {
+                if env.instance(&instance.constraint()).is_some() {
+                    return Err(Error::InvalidInstance { 
+                        token: token.clone(), 
+                        msg: format!("There already is an instance {}", instance.constraint().fg(Color::Cyan))
+                    })
+                }
+
+                env.instances.push(instance.clone());
+                Ok(())
+            },
+
+            Node::TypeAlias { alias, token } => {
+                if env.type_exists(&alias.name) {
+                    return Err(Error::InvalidTypeAlias { 
+                        token: token.clone(), 
+                        msg: format!("The type {} already exists", (&alias.name).fg(Color::Cyan))
+                    })
+                }
+
+                env.aliases.insert(alias.name.clone(), alias.clone());
+                Ok(())
+            },
         }
     }
 
@@ -103,7 +268,174 @@ impl Node {
             name: name.to_string(), 
             token: Token::Newline, // TODO It does not matter, but this is hacky anyways
             arguments: TypeList::new(),
-            returns: TypeList::new()
+            returns: TypeList::new(),
+            constraints: TypeList::new()
+        }
+    }
+
+    /// Replaces the type variables, e.g. with everything known about them at the end
+    /// of a program or to inline a generic function for the types it is called with.
+    /// Declarations are left as they are
+    pub fn substitute(&self, vars: &Substitution) -> Node {
+        let nodes = |nodes: &Vec<Node>| nodes
+            .iter()
+            .map(|node| node.substitute(vars))
+            .collect::<Vec<Node>>();
+
+        match self.clone() {
+            Node::Assigment { name, token, typ } => 
+                Node::Assigment { name, token, typ: typ.substitute(vars) },
+
+            Node::Variable { name, token, typ } => 
+                Node::Variable { name, token, typ: typ.substitute(vars) },
+
+            Node::Call { name, token, arguments, returns, constraints } => Node::Call { 
+                name, 
+                token, 
+                arguments: arguments.substitute(vars), 
+                returns: returns.substitute(vars), 
+                constraints: constraints.substitute(vars) 
+            },
+
+            Node::Literal { typ, value, token } => {
+                let value = match &value {
+                    Literal::Interpolation(parts) => Literal::Interpolation(parts
+                        .iter()
+                        .map(|part| match part {
+                            InterpolationPart::Code(code) => InterpolationPart::Code(nodes(code)),
+
+                            InterpolationPart::Text(_) => part.clone()
+                        })
+                        .collect()),
+
+                    Literal::List(elems) => Literal::List(nodes(elems)),
+
+                    Literal::Tuple(elems) => Literal::Tuple(nodes(elems)),
+
+                    Literal::Function(sig, body) => Literal::Function(sig.substitute(vars), nodes(body)),
+
+                    _ => value
+                };
+
+                Node::Literal { typ: typ.substitute(vars), value, token }
+            },
+
+            Node::Match { union, arms, token, arguments, returns } => Node::Match { 
+                union, 
+                arms: arms.iter().map(nodes).collect(), 
+                token, 
+                arguments: arguments.substitute(vars), 
+                returns: returns.substitute(vars) 
+            },
+
+            Node::Try { token, arguments, returns } => Node::Try { 
+                token, 
+                arguments: arguments.substitute(vars), 
+                returns: returns.substitute(vars) 
+            },
+
+            declaration => declaration
         }
     }
 }
+
+/// Applies the stack effect to the stack, the types of the effect which 
+/// were bound are returned without the rest of the stack. On mismatch,
+/// the stack is returned
+fn apply_effect(env: &mut TypeEnv, arguments: &TypeList, returns: &TypeList) -> Result<(TypeList, TypeList), TypeList> {
+    let arg_len = arguments.len();
+    let ret_len = returns.len();
+
+    if arg_len > env.stack.len() {
+        return Err(env.stack.clone())
+    }
+
+    // Nothing is bound unless the whole effect fits
+    let mut tenv = env.clone();
+    let mut stack_args = Vec::with_capacity(arg_len);
+
+    for arg in arguments.iter().rev() {
+        let stack_arg = tenv.stack.pop().unwrap();
+
+        tenv.unify(arg, &stack_arg)
+            .map_err(|_| env.stack.clone())?;
+
+        stack_args.push(stack_arg);
+    }
+
+    stack_args.reverse();
+
+    // The row variable stands for the rest of the stack
+    if let Some(row) = arguments.row() {
+        let rest = tenv.stack.clone();
+
+        tenv.unify_lists(&TypeList::new().with_row(row.clone()), &rest)
+            .map_err(|_| env.stack.clone())?;
+    }
+
+    let concrete_returns = tenv.apply_list(returns);
+
+    match returns.row() {
+        // The bound row variable already contains the rest of the stack
+        Some(row) if tenv.substitution.row(row.name()).is_some() => 
+            tenv.stack = concrete_returns.clone(),
+
+        _ => tenv.stack.extend(concrete_returns.clone())
+    }
+
+    // The values keep the types they have on the stack, a function value is 
+    // called with its own effect rather than the row extended one of the word
+    let arguments = tenv.apply_list(&TypeList::from(stack_args));
+    let returns = TypeList::from(concrete_returns.clone_top(ret_len));
+
+    *env = tenv;
+
+    Ok((arguments, returns))
+}
+
+/// How many values the effect takes, including the ones its row variable stands for once 
+/// the arguments are bound, e.g. the ones a function value passed to `call` takes
+fn effect_depth(env: &TypeEnv, arguments: &TypeList) -> usize {
+    let len = arguments.len();
+
+    let Some(row) = arguments.row() else {
+        return len
+    };
+
+    if env.stack.len() < len {
+        return len
+    }
+
+    let mut tenv = env.clone();
+    let top = TypeList::from(env.stack.clone_top(len));
+
+    if tenv.unify_lists(&TypeList::from(arguments.vec().clone()), &top).is_err() {
+        return len
+    }
+
+    len + tenv.apply_list(&TypeList::new().with_row(row.clone())).len()
+}
+
+/// The arguments of a word for showing them, with the names they are declared with
+fn expected_arguments(env: &TypeEnv, arguments: &TypeList) -> TypeList {
+    let sig = TypedSignature::new(env.apply_list(arguments), TypeList::new()).generalize();
+
+    env.resugar_list(sig.arguments())
+}
+
+/// Binds the generated words of a type declaration
+fn declare_words<F>(env: &mut TypeEnv, words: Vec<(String, TypedSignature)>, invalid: F) -> Result<(), Error> 
+    where F: Fn(String) -> Error
+{
+    for (i, (name, _)) in words.iter().enumerate() {
+        if env.bindings.contains_key(name) || words[..i].iter().any(|(other, _)| other == name) {
+            return Err(invalid(format!("The word {} already exists", name.fg(Color::Cyan))))
+        }
+    }
+
+    for (name, sig) in words {
+        env.bindings.insert(name, Scheme::closed(sig.into()));
+    }
+
+    Ok(())
+}
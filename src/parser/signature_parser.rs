@@ -1,15 +1,23 @@
-use std::{collections::HashMap, str::FromStr, sync::{Arc, Mutex}};
+use std::{collections::HashMap, str::FromStr, fmt::{Display, Debug}};
+
+use ariadne::{Color, Fmt};
 
 use crate::{lexer::sig_lexer::{LexedSignature, SignatureElement, lex_signature}, error::Error};
 
-use super::{types::{typ::{VarContent, Type}, typelist::TypeList, *}};
+use super::{types::{typ::Type, typelist::{TypeList, RowVar}, alias::Aliases, substitution::Substitution, type_env::TypeEnv, *}};
 
-#[derive(Debug, Clone, Default)]
-pub struct TypedSignature(pub TypeList, pub TypeList);
+/// The arguments, the returns and the constraints, e.g. `Num['a]`
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct TypedSignature(pub TypeList, pub TypeList, pub TypeList);
 
 impl TypedSignature {
     pub fn new(args: TypeList, rets: TypeList) -> Self {
-        TypedSignature(args, rets)
+        TypedSignature(args, rets, TypeList::new())
+    }
+
+    pub fn with_constraints(mut self, constraints: TypeList) -> Self {
+        self.2 = constraints;
+        self
     }
 
     pub fn arguments(&self) -> &TypeList {
@@ -19,47 +27,188 @@ impl TypedSignature {
     pub fn returns(&self) -> &TypeList {
         &self.1
     }
+
+    pub fn constraints(&self) -> &TypeList {
+        &self.2
+    }
+
+    pub fn substitute(&self, subst: &Substitution) -> TypedSignature {
+        TypedSignature(self.0.substitute(subst), self.1.substitute(subst), self.2.substitute(subst))
+    }
+
+    /// The names of all type and row variables
+    pub fn variables(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+
+        self.0.free_vars(&mut vars);
+        self.1.free_vars(&mut vars);
+        self.2.free_vars(&mut vars);
+
+        vars
+    }
+
+    /// The signature with fresh variables, together with the renaming
+    pub fn instantiate(&self, env: &mut TypeEnv) -> (TypedSignature, Substitution) {
+        let fresh = Substitution::fresh(&self.variables(), env);
+
+        (self.substitute(&fresh), fresh)
+    }
+
+    /// The types the variables of this signature stand for in `actual`, 
+    /// e.g. the signature a generic function is called with
+    pub fn match_vars(&self, actual: &TypedSignature) -> Substitution {
+        let mut vars = HashMap::new();
+
+        self.0.iter().zip(actual.0.iter())
+            .chain(self.1.iter().zip(actual.1.iter()))
+            .for_each(|(generic, actual)| generic.match_vars(actual, &mut vars));
+
+        vars.into()
+    }
+
+    /// Renames the type variables to 'a, 'b, ... for showing the signature
+    pub fn generalize(&self) -> TypedSignature {
+        let mut vars = HashMap::new();
+
+        TypedSignature(
+            generalize_list(&self.0, &mut vars),
+            generalize_list(&self.1, &mut vars),
+            generalize_list(&self.2, &mut vars)
+        )
+    }
 }
 
-fn build_signature(elems: Vec<SignatureElement>, vars: &mut HashMap<String, VarContent>) -> Vec<Type> {
-    let convert = |elem| match elem {
-        SignatureElement::Kind(name, inner) =>
-            typ(&name, build_signature(inner, vars)),
-
-        SignatureElement::Variable(name) => {
-            if let Some(content) = vars.get(&name) {
-                var_type_raw(&name.to_string(), content.clone())
-            }
-            else {
-                let content = Arc::new(Mutex::new(None));
-                vars.insert(name.clone(), content.clone());
-                Type::Variable(name, content)
-            }
-        },
-    };
-
-    elems.into_iter()
-        .map(convert)
-        .collect()
+fn generalize_list(list: &TypeList, vars: &mut HashMap<String, Type>) -> TypeList {
+    let types = list
+        .iter()
+        .map(|typ| generalize_type(typ, vars))
+        .collect::<Vec<Type>>();
+
+    match list.row() {
+        Some(row) => TypeList::from(types).with_row(row.clone()),
+
+        None => TypeList::from(types)
+    }
+}
+
+fn generalize_type(typ: &Type, vars: &mut HashMap<String, Type>) -> Type {
+    match typ {
+        Type::Kind(name, types) => 
+            Type::Kind(name.clone(), generalize_list(types, vars)),
+
+        Type::Variable(name) => {
+            let count = vars.len();
+
+            vars.entry(name.clone())
+                .or_insert_with(|| var_type(&var_name(count)))
+                .clone()
+        }
+    }
+}
+
+// a, b, ..., z, a1, b1, ...
+fn var_name(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+
+    match n / 26 {
+        0 => letter.to_string(),
+
+        round => format!("{letter}{round}")
+    }
+}
+
+impl Display for TypedSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = |list: &TypeList| if list.is_empty() && list.row().is_none() {
+            String::new()
+        }
+        else {
+            format!("{list} ")
+        };
+
+        let constraints = if self.2.is_empty() {
+            String::new()
+        }
+        else {
+            format!("{} => ", self.2)
+        };
+
+        write!(f, "( {constraints}{}-- {})", side(&self.0), side(&self.1))
+    }
+}
+
+// The nested type lists are just noise
+impl Debug for TypedSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+fn build_signature(elems: Vec<SignatureElement>, aliases: &Aliases) -> Result<TypeList, String> {
+    let mut row = None;
+    let mut types = Vec::new();
+
+    for elem in elems {
+        match elem {
+            SignatureElement::Kind(name, inner) => {
+                let inner = build_signature(inner, aliases)?;
+
+                match aliases.get(&name) {
+                    Some(alias) if inner.len() != alias.params.len() || inner.row().is_some() =>
+                        return Err(format!(
+                            "The type {} expects {} type arguments, but got {}",
+                            (&name).fg(Color::Cyan),
+                            alias.params.len(),
+                            inner.len()
+                        )),
+
+                    Some(alias) => types.push(alias.expand(inner.vec().clone())),
+
+                    None => types.push(Type::Kind(name, inner))
+                }
+            },
+
+            SignatureElement::Variable(name) => types.push(var_type(&name)),
+
+            SignatureElement::Row(name) => row = Some(RowVar(name)),
+        }
+    }
+
+    match row {
+        Some(row) => Ok(TypeList::from(types).with_row(row)),
+
+        None => Ok(TypeList::from(types))
+    }
+}
+
+/// Builds a single type
+pub fn build_type(elem: SignatureElement, aliases: &Aliases) -> Result<Type, String> {
+    Ok(build_signature(vec![elem], aliases)?
+        .pop()
+        .unwrap())
+}
+
+impl TypedSignature {
+    /// Builds the signature, the given aliases are expanded
+    pub fn build(sig: LexedSignature, aliases: &Aliases) -> Result<Self, String> {
+        Ok(TypedSignature(
+            build_signature(sig.get_args().clone(), aliases)?,
+            build_signature(sig.get_returns().clone(), aliases)?,
+            build_signature(sig.get_constraints().clone(), aliases)?
+        ))
+    }
 }
 
 impl From<LexedSignature> for TypedSignature {
     fn from(sig: LexedSignature) -> Self {
-        let mut vars = HashMap::new();
-
-        TypedSignature::new(
-            TypeList::from(build_signature(sig.get_args().clone(), &mut vars)),
-            TypeList::from(build_signature(sig.get_returns().clone(), &mut vars))
-        )
+        TypedSignature::build(sig, &Aliases::new())
+            .expect("Only aliases can make a signature invalid")
     }
 }
 
 impl From<TypedSignature> for Type {
     fn from(val: TypedSignature) -> Self {
-        func_type(
-            val.0.vec().clone(),
-            val.1.vec().clone()
-        )
+        constrained_func_type(val.0, val.1, val.2)
     }
 }
 
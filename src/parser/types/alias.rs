@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use super::{typ::Type, var_types};
+
+pub type Aliases = HashMap<String, Alias>;
+
+/// Declared with `type strings list[str]` or `type pred['a] fun[arg['a] ret[bool]]`,
+/// aliases are expanded while building signatures
+#[derive(Clone, Debug)]
+pub struct Alias {
+    pub name: String,
+
+    pub params: Vec<String>,
+
+    pub typ: Type
+}
+
+impl Alias {
+    pub fn new(name: String, params: Vec<String>, typ: Type) -> Self {
+        Alias { name, params, typ }
+    }
+
+    pub fn expand(&self, args: Vec<Type>) -> Type {
+        let vars = self.params
+            .iter()
+            .cloned()
+            .zip(args)
+            .collect::<HashMap<String, Type>>();
+
+        self.typ.substitute(&vars.into())
+    }
+
+    /// The arguments of the alias, if `typ` is one of its expansions
+    pub fn matches(&self, typ: &Type) -> Option<Vec<Type>> {
+        let mut args = HashMap::new();
+
+        if !match_template(&self.typ, typ, &self.params, &mut args) {
+            return None
+        }
+
+        let args = self.params
+            .iter()
+            .zip(var_types(&self.params))
+            .map(|(param, var)| args.remove(param).unwrap_or(var))
+            .collect();
+
+        Some(args)
+    }
+}
+
+fn match_template(template: &Type, typ: &Type, params: &[String], args: &mut HashMap<String, Type>) -> bool {
+    match (template, typ) {
+        (Type::Variable(name), _) if params.contains(name) => match args.get(name) {
+            Some(arg) => arg == typ,
+
+            None => {
+                args.insert(name.clone(), typ.clone());
+                true
+            }
+        },
+
+        (Type::Kind(a, types_a), Type::Kind(b, types_b)) => 
+            a == b 
+                && types_a.len() == types_b.len()
+                && types_a.row().is_none() == types_b.row().is_none()
+                && types_a.iter()
+                    .zip(types_b.iter())
+                    .all(|(a, b)| match_template(a, b, params, args)),
+
+        _ => false
+    }
+}
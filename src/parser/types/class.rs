@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::parser::{signature_parser::TypedSignature, node::Node};
+
+use super::{typ::Type, typelist::TypeList, typ, var_type};
+
+pub type Classes = HashMap<String, Class>;
+pub type Instances = Vec<Instance>;
+
+/// A type class declared with `class Show['a] [show: ( 'a -- str )]`. Its
+/// methods are words constrained by the class, e.g. `( Show['a] => 'a -- str )`
+#[derive(Clone, Debug)]
+pub struct Class {
+    pub name: String,
+
+    /// The type variable standing for the instance
+    pub param: String,
+
+    pub methods: Vec<(String, TypedSignature)>
+}
+
+impl Class {
+    pub fn new(name: String, param: String, methods: Vec<(String, TypedSignature)>) -> Self {
+        Class { name, param, methods }
+    }
+
+    /// The constraint that `typ` is an instance of this class
+    pub fn constraint(&self, instance: Type) -> Type {
+        typ(&self.name, vec![instance])
+    }
+
+    pub fn method(&self, name: &str) -> Option<&TypedSignature> {
+        self.methods
+            .iter()
+            .find(|(method, _)| method == name)
+            .map(|(_, sig)| sig)
+    }
+
+    /// The methods with the constraint on the type variable of the class
+    pub fn words(&self) -> Vec<(String, TypedSignature)> {
+        let constraint = TypeList::from(vec![self.constraint(var_type(&self.param))]);
+
+        self.methods
+            .iter()
+            .map(|(name, sig)| (name.clone(), sig.clone().with_constraints(constraint.clone())))
+            .collect()
+    }
+
+    /// The signature the method has for the given instance
+    pub fn method_signature(&self, name: &str, typ: &Type) -> Option<TypedSignature> {
+        let vars = HashMap::from([(self.param.clone(), typ.clone())]).into();
+
+        self.method(name).map(|sig| TypedSignature::new(
+            sig.arguments().substitute(&vars),
+            sig.returns().substitute(&vars)
+        ))
+    }
+}
+
+/// Declared with `instance Show[point] [show: { ... }]`, the bodies 
+/// of the methods are inlined wherever they are called
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub class: String,
+
+    pub typ: Type,
+
+    pub methods: Vec<(String, Vec<Node>)>
+}
+
+impl Instance {
+    pub fn new(class: String, typ: Type, methods: Vec<(String, Vec<Node>)>) -> Self {
+        Instance { class, typ, methods }
+    }
+
+    /// The constraint satisfied by this instance, e.g. `Show[point]`
+    pub fn constraint(&self) -> Type {
+        typ(&self.class, vec![self.typ.clone()])
+    }
+
+    pub fn method(&self, name: &str) -> Option<&Vec<Node>> {
+        self.methods
+            .iter()
+            .find(|(method, _)| method == name)
+            .map(|(_, body)| body)
+    }
+}
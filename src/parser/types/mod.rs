@@ -1,10 +1,27 @@
 pub mod type_env;
 pub mod typ;
 pub mod typelist;
+pub mod record;
+pub mod tagged_union;
+pub mod alias;
+pub mod class;
+pub mod substitution;
+pub mod scheme;
 
-use std::sync::{Mutex, Arc};
+use std::{sync::atomic::{AtomicU32, Ordering}, collections::HashMap};
 
-use self::{typ::{Type, VarContent}, typelist::TypeList};
+use self::{typ::Type, typelist::TypeList, substitution::Substitution};
+
+// Fresh variables have to be distinct everywhere, as substitutions outlive nested scopes
+static FRESH_VAR_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A new name based on the given one, e.g. `a12` for `a` or `a3`
+pub fn fresh_name(base: &str) -> String {
+    let id = FRESH_VAR_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let base = base.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    format!("{base}{id}")
+}
 
 pub fn typ(name: &str, inner: Vec<Type>) -> Type {
     Type::Kind(name.to_string(), TypeList::from(inner))
@@ -12,8 +29,21 @@ pub fn typ(name: &str, inner: Vec<Type>) -> Type {
 
 pub const QUOTE_TYPE_NAME: &str = "str";
 pub const NUMBER_TYPE_NAME: &str = "num";
+pub const INTEGER_TYPE_NAME: &str = "int";
+pub const BOOL_TYPE_NAME: &str = "bool";
 pub const LIST_TYPE_NAME: &str = "list";
 pub const FUNC_TYPE_NAME: &str = "fun";
+pub const TUPLE_TYPE_NAME: &str = "tuple";
+
+/// Declared by the stdlib, `try` returns early with their failures
+pub const OPTION_TYPE_NAME: &str = "option";
+pub const RESULT_TYPE_NAME: &str = "result";
+
+/// Types known to the compiler, including the ones only used for FFI
+pub const BUILTIN_TYPE_NAMES: [&str; 13] = [
+    QUOTE_TYPE_NAME, NUMBER_TYPE_NAME, INTEGER_TYPE_NAME, BOOL_TYPE_NAME, LIST_TYPE_NAME, FUNC_TYPE_NAME, TUPLE_TYPE_NAME,
+    "ci32", "ci64", "ci128", "cstr", "pointer", "args"
+];
 
 pub fn quote_type() -> Type {
    typ(QUOTE_TYPE_NAME, vec![])
@@ -23,21 +53,66 @@ pub fn number_type() -> Type {
     typ(NUMBER_TYPE_NAME, vec![])
 }
 
+pub fn integer_type() -> Type {
+    typ(INTEGER_TYPE_NAME, vec![])
+}
+
+pub fn bool_type() -> Type {
+    typ(BOOL_TYPE_NAME, vec![])
+}
+
 pub fn list_type(inner: Type) -> Type {
     typ(LIST_TYPE_NAME, vec![inner])
 }
 
-pub fn func_type(args: Vec<Type>, result: Vec<Type>) -> Type {
+pub fn tuple_type(elems: Vec<Type>) -> Type {
+    typ(TUPLE_TYPE_NAME, elems)
+}
+
+pub fn func_type(args: TypeList, result: TypeList) -> Type {
+    typ(FUNC_TYPE_NAME, vec![
+        Type::Kind("arg".to_string(), args),
+        Type::Kind("ret".to_string(), result)
+    ])
+}
+
+/// The constraints are only added if there are any, so that 
+/// unconstrained functions keep their usual type
+pub fn constrained_func_type(args: TypeList, result: TypeList, constraints: TypeList) -> Type {
+    if constraints.is_empty() {
+        return func_type(args, result)
+    }
+
     typ(FUNC_TYPE_NAME, vec![
-        typ("arg", args),
-        typ("ret", result)
+        Type::Kind("arg".to_string(), args),
+        Type::Kind("ret".to_string(), result),
+        Type::Kind("where".to_string(), constraints)
     ])
 }
 
-pub fn var_type(name: &str, value: Option<Type>) -> Type {
-    Type::Variable(name.to_string(), Arc::new(Mutex::new(value)))
+pub fn var_type(name: &str) -> Type {
+    Type::Variable(name.to_string())
 }
 
-pub fn var_type_raw(name: &str, value: VarContent) -> Type {
-    Type::Variable(name.to_string(), value)
+/// The type variables standing for the parameters of a generic type
+pub fn var_types(params: &[String]) -> Vec<Type> {
+    params
+        .iter()
+        .map(|param| var_type(param))
+        .collect()
+}
+
+/// Maps the parameters of a generic type to the arguments of `typ`, e.g. 
+/// `'a` to `num` for `option[num]`
+pub fn type_arguments(params: &[String], typ: &Type) -> Substitution {
+    match typ {
+        Type::Kind(_, args) => params
+            .iter()
+            .cloned()
+            .zip(args.iter().cloned())
+            .collect::<HashMap<String, Type>>()
+            .into(),
+
+        Type::Variable(..) => Substitution::default()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::parser::signature_parser::TypedSignature;
+
+use super::{typ::Type, typelist::TypeList, typ, var_types};
+
+pub type Records = HashMap<String, Record>;
+
+/// A record declared with `record point [x: num y: num]`. Values are pointers
+/// to the fields, each one occupying 8 bytes in the declared order
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub name: String,
+
+    /// The type variables of a generic record, `'a` and `'b` for `pair['a]['b]`
+    pub params: Vec<String>,
+
+    pub fields: Vec<(String, Type)>,
+
+    /// The kinds of the generated words by their names, every field access
+    /// and construction looks them up
+    word_kinds: HashMap<String, RecordWord>
+}
+
+/// The words generated for every record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordWord {
+    /// `point 1 2`, takes the fields in the declared order
+    Constructor,
+
+    /// `point.x p`, the field index
+    Getter(usize),
+
+    /// `point.with-x p 3`, returns an updated copy
+    Updater(usize)
+}
+
+impl Record {
+    pub fn new(name: String, params: Vec<String>, fields: Vec<(String, Type)>) -> Self {
+        let mut record = Record { name, params, fields, word_kinds: HashMap::new() };
+
+        record.word_kinds = record
+            .words()
+            .into_iter()
+            .map(|(name, word, _)| (name, word))
+            .collect();
+
+        record
+    }
+
+    pub fn typ(&self) -> Type {
+        typ(&self.name, var_types(&self.params))
+    }
+
+    pub fn size(&self) -> usize {
+        self.fields.len() * 8
+    }
+
+    pub fn getter_name(&self, field: &str) -> String {
+        format!("{}.{field}", self.name)
+    }
+
+    pub fn updater_name(&self, field: &str) -> String {
+        format!("{}.with-{field}", self.name)
+    }
+
+    /// The names of all generated words with their signatures
+    pub fn words(&self) -> Vec<(String, RecordWord, TypedSignature)> {
+        // The first field has to be on top, so it is the first one written after the name
+        let fields = self.fields
+            .iter()
+            .rev()
+            .map(|(_, typ)| typ.clone())
+            .collect::<Vec<Type>>();
+
+        let constructor = (
+            self.name.clone(),
+            RecordWord::Constructor,
+            TypedSignature::new(TypeList::from(fields), TypeList::from(vec![self.typ()]))
+        );
+
+        let getters = self.fields
+            .iter()
+            .enumerate()
+            .map(|(i, (name, typ))| (
+                self.getter_name(name),
+                RecordWord::Getter(i),
+                TypedSignature::new(TypeList::from(vec![self.typ()]), TypeList::from(vec![typ.clone()]))
+            ));
+
+        let updaters = self.fields
+            .iter()
+            .enumerate()
+            .map(|(i, (name, typ))| (
+                self.updater_name(name),
+                RecordWord::Updater(i),
+                TypedSignature::new(TypeList::from(vec![typ.clone(), self.typ()]), TypeList::from(vec![self.typ()]))
+            ));
+
+        std::iter::once(constructor)
+            .chain(getters)
+            .chain(updaters)
+            .collect()
+    }
+
+    pub fn word(&self, name: &str) -> Option<RecordWord> {
+        self.word_kinds.get(name).copied()
+    }
+}
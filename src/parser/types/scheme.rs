@@ -0,0 +1,60 @@
+use super::{typ::Type, type_env::TypeEnv, substitution::Substitution};
+
+/// A type which is generic over some of its variables. Every use of a
+/// binding gets fresh ones for them, e.g. `dup` for `( 'a -- 'a 'a )`
+#[derive(Clone, Debug)]
+pub struct Scheme {
+    /// The names of the type and row variables
+    pub vars: Vec<String>,
+
+    pub typ: Type
+}
+
+impl Scheme {
+    /// Generic over all of its variables, like the words of the stdlib
+    pub fn closed(typ: Type) -> Self {
+        let mut vars = Vec::new();
+        typ.free_vars(&mut vars);
+
+        Scheme { vars, typ }
+    }
+
+    /// Generic over the variables which are not used anywhere else in the
+    /// environment, the ones of the inferred arguments have to stay the same
+    pub fn generalize(typ: &Type, env: &TypeEnv) -> Self {
+        let typ = env.apply(typ);
+        let env_vars = env.free_vars();
+
+        let mut vars = Vec::new();
+        typ.free_vars(&mut vars);
+        vars.retain(|var| !env_vars.contains(var));
+
+        Scheme { vars, typ }
+    }
+
+    pub fn instantiate(&self, env: &mut TypeEnv) -> Type {
+        if self.vars.is_empty() {
+            return self.typ.clone()
+        }
+
+        let fresh = Substitution::fresh(&self.vars, env);
+        self.typ.substitute(&fresh)
+    }
+
+    /// Only the variables it is not generic over are replaced
+    pub fn substitute(&self, subst: &Substitution) -> Self {
+        Scheme { vars: self.vars.clone(), typ: self.typ.substitute(subst) }
+    }
+
+    /// The variables it is not generic over
+    pub fn free_vars(&self, vars: &mut Vec<String>) {
+        let mut own = Vec::new();
+        self.typ.free_vars(&mut own);
+
+        for var in own {
+            if !self.vars.contains(&var) && !vars.contains(&var) {
+                vars.push(var);
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use ariadne::{Color, Fmt};
+
+use super::{typ::Type, typelist::{TypeList, RowVar}, type_env::TypeEnv};
+
+/// What is known about the type and row variables. Bound variables never
+/// occur in the types they are bound to, so substituting once is enough
+#[derive(Clone, Debug, Default)]
+pub struct Substitution {
+    vars: HashMap<String, Type>,
+    rows: HashMap<String, TypeList>
+}
+
+impl Substitution {
+    /// Fresh variables for the given ones, e.g. to instantiate a generic function.
+    /// The names of row variables start with two dots
+    pub fn fresh(names: &[String], env: &mut TypeEnv) -> Self {
+        let mut fresh = Substitution::default();
+
+        for name in names {
+            if name.starts_with("..") {
+                fresh.rows.insert(name.clone(), TypeList::new().with_row(env.new_row(name)));
+            }
+            else {
+                fresh.vars.insert(name.clone(), env.new_var(name));
+            }
+        }
+
+        fresh
+    }
+
+    /// Replaces the fresh variables of a renaming by the original ones again
+    pub fn inverse(&self) -> Self {
+        let vars = self.vars
+            .iter()
+            .filter_map(|(name, typ)| match typ {
+                Type::Variable(fresh) => Some((fresh.clone(), Type::Variable(name.clone()))),
+
+                Type::Kind(..) => None
+            })
+            .collect();
+
+        let rows = self.rows
+            .iter()
+            .filter_map(|(name, list)| match list.row() {
+                Some(fresh) if list.is_empty() =>
+                    Some((fresh.name().clone(), TypeList::new().with_row(RowVar(name.clone())))),
+
+                _ => None
+            })
+            .collect();
+
+        Substitution { vars, rows }
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Type> {
+        self.vars.get(name)
+    }
+
+    pub fn row(&self, name: &str) -> Option<&TypeList> {
+        self.rows.get(name)
+    }
+
+    pub fn unify(&mut self, a: &Type, b: &Type) -> Result<(), String> {
+        use Type::*;
+
+        let a = a.substitute(self);
+        let b = b.substitute(self);
+
+        match (&a, &b) {
+            (Variable(x), Variable(y)) if x == y => Ok(()),
+
+            (Variable(var), other) | (other, Variable(var)) => {
+                if other.occurs(var) {
+                    return Err(format!(
+                        "Occurs Check: Type {} contains typevar {}",
+                        other.fg(Color::Cyan),
+                        format!("'{var}").fg(Color::Cyan),
+                    ))
+                }
+
+                self.bind_var(var, other.clone());
+                Ok(())
+            },
+
+            (Kind(name_a, types_a), Kind(name_b, types_b)) if name_a == name_b => {
+                if a.has_row_vars() != b.has_row_vars() {
+                    if let Some(extended) = a.row_extended() {
+                        return self.unify(&extended, &b)
+                    }
+
+                    if let Some(extended) = b.row_extended() {
+                        return self.unify(&a, &extended)
+                    }
+                }
+
+                self.unify_lists(types_a, types_b)
+            },
+
+            _ => Err(format!(
+                "Type Mismatch: {} and {}",
+                a.fg(Color::Cyan),
+                b.fg(Color::Cyan)
+            ))
+        }
+    }
+
+    /// Lists are unified from the top, remaining types are bound to
+    /// the row variable of the other list
+    pub fn unify_lists(&mut self, a: &TypeList, b: &TypeList) -> Result<(), String> {
+        let a = a.substitute(self);
+        let b = b.substitute(self);
+
+        let common = a.len().min(b.len());
+        let rest_a = TypeList::from(a[..(a.len() - common)].to_vec());
+        let rest_b = TypeList::from(b[..(b.len() - common)].to_vec());
+        let rest_a = with_row_of(rest_a, &a);
+        let rest_b = with_row_of(rest_b, &b);
+
+        a[rest_a.len()..]
+            .iter()
+            .zip(&b[rest_b.len()..])
+            .try_for_each(|(a, b)| self.unify(a, b))?;
+
+        // Unifying the common types may have bound the rows
+        let rest_a = rest_a.substitute(self);
+        let rest_b = rest_b.substitute(self);
+
+        match (rest_a.row(), rest_b.row()) {
+            _ if rest_a == rest_b => Ok(()),
+
+            (Some(row), _) if rest_a.is_empty() && !rest_b.occurs(row.name()) => {
+                self.bind_row(row.name(), rest_b.clone());
+                Ok(())
+            },
+
+            (_, Some(row)) if rest_b.is_empty() && !rest_a.occurs(row.name()) => {
+                self.bind_row(row.name(), rest_a.clone());
+                Ok(())
+            },
+
+            _ => Err(format!(
+                "Stack Mismatch: {} and {}",
+                a.fg(Color::Cyan),
+                b.fg(Color::Cyan)
+            ))
+        }
+    }
+
+    fn bind_var(&mut self, name: &str, typ: Type) {
+        let single = Substitution {
+            vars: HashMap::from([(name.to_string(), typ.clone())]),
+            rows: HashMap::new()
+        };
+
+        self.update(&single);
+        self.vars.insert(name.to_string(), typ);
+    }
+
+    fn bind_row(&mut self, name: &str, list: TypeList) {
+        let single = Substitution {
+            vars: HashMap::new(),
+            rows: HashMap::from([(name.to_string(), list.clone())])
+        };
+
+        self.update(&single);
+        self.rows.insert(name.to_string(), list);
+    }
+
+    /// Keeps the bound types free of the newly bound variable
+    fn update(&mut self, single: &Substitution) {
+        for typ in self.vars.values_mut() {
+            *typ = typ.substitute(single);
+        }
+
+        for list in self.rows.values_mut() {
+            *list = list.substitute(single);
+        }
+    }
+}
+
+/// The parameters of a generic type have no rows
+impl From<HashMap<String, Type>> for Substitution {
+    fn from(vars: HashMap<String, Type>) -> Self {
+        Substitution { vars, rows: HashMap::new() }
+    }
+}
+
+fn with_row_of(rest: TypeList, list: &TypeList) -> TypeList {
+    match list.row() {
+        Some(row) => rest.with_row(row.clone()),
+
+        None => rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::types::{typ::Type, typelist::{TypeList, RowVar}, number_type, integer_type, list_type, var_type};
+
+    use super::Substitution;
+
+    fn row(name: &str) -> RowVar {
+        RowVar(name.to_string())
+    }
+
+    #[test]
+    fn variable_with_itself() {
+        let mut subst = Substitution::default();
+
+        assert_eq!(subst.unify(&var_type("a"), &var_type("a")), Ok(()));
+        assert_eq!(subst.var("a"), None);
+    }
+
+    #[test]
+    fn variable_with_concrete_type() {
+        let mut subst = Substitution::default();
+
+        assert_eq!(subst.unify(&var_type("a"), &number_type()), Ok(()));
+        assert_eq!(subst.unify(&list_type(number_type()), &list_type(var_type("b"))), Ok(()));
+
+        assert_eq!(subst.var("a"), Some(&number_type()));
+        assert_eq!(subst.var("b"), Some(&number_type()));
+
+        // Once bound, the variable only unifies with its type
+        assert!(subst.unify(&var_type("a"), &integer_type()).is_err());
+    }
+
+    #[test]
+    fn occurs_check() {
+        let mut subst = Substitution::default();
+
+        let err = subst.unify(&var_type("a"), &list_type(var_type("a"))).unwrap_err();
+
+        assert!(err.contains("Occurs Check"), "{err}");
+        assert_eq!(subst.var("a"), None);
+
+        // Also when the variable is only reached through the substitution
+        let mut subst = Substitution::default();
+
+        assert_eq!(subst.unify(&var_type("b"), &list_type(var_type("a"))), Ok(()));
+        assert!(subst.unify(&var_type("a"), &list_type(var_type("b"))).is_err());
+    }
+
+    #[test]
+    fn row_binds_the_rest_of_a_longer_list() {
+        let mut subst = Substitution::default();
+
+        // ( ..r num ) with ( int str num ), the top types are unified first
+        let a = TypeList::from(vec![var_type("x")]).with_row(row("..r"));
+        let b = TypeList::from(vec![integer_type(), Type::Kind("str".to_string(), TypeList::new()), number_type()]);
+
+        assert_eq!(subst.unify_lists(&a, &b), Ok(()));
+
+        assert_eq!(subst.var("x"), Some(&number_type()));
+        assert_eq!(subst.row("..r"), Some(&TypeList::from(b[..2].to_vec())));
+        assert_eq!(a.substitute(&subst), b);
+    }
+
+    #[test]
+    fn rows_of_both_lists() {
+        let mut subst = Substitution::default();
+
+        // ( ..r int num ) with ( ..s num ), so ..s is ( ..r int )
+        let a = TypeList::from(vec![integer_type(), number_type()]).with_row(row("..r"));
+        let b = TypeList::from(vec![number_type()]).with_row(row("..s"));
+
+        assert_eq!(subst.unify_lists(&a, &b), Ok(()));
+        assert_eq!(subst.row("..s"), Some(&TypeList::from(vec![integer_type()]).with_row(row("..r"))));
+        assert_eq!(a.substitute(&subst), b.substitute(&subst));
+    }
+
+    #[test]
+    fn longer_list_without_row() {
+        let mut subst = Substitution::default();
+
+        let a = TypeList::from(vec![number_type()]);
+        let b = TypeList::from(vec![integer_type(), number_type()]);
+
+        let err = subst.unify_lists(&a, &b).unwrap_err();
+        assert!(err.contains("Stack Mismatch"), "{err}");
+
+        // A row does not occur in what it stands for
+        let a = TypeList::new().with_row(row("..r"));
+        let b = TypeList::from(vec![list_type(Type::Kind("fun".to_string(), TypeList::new().with_row(row("..r"))))]);
+
+        assert!(subst.unify_lists(&a, &b).is_err());
+    }
+
+    #[test]
+    fn bindings_are_composed() {
+        let mut subst = Substitution::default();
+
+        // 'a = list['b], then 'b = 'c and 'c = num
+        assert_eq!(subst.unify(&var_type("a"), &list_type(var_type("b"))), Ok(()));
+        assert_eq!(subst.unify(&var_type("b"), &var_type("c")), Ok(()));
+        assert_eq!(subst.unify(&var_type("c"), &number_type()), Ok(()));
+
+        // Earlier bindings are updated, so substituting once is enough
+        assert_eq!(subst.var("a"), Some(&list_type(number_type())));
+        assert_eq!(subst.var("b"), Some(&number_type()));
+        assert_eq!(var_type("a").substitute(&subst), list_type(number_type()));
+
+        // The same for rows bound to lists containing variables
+        let mut subst = Substitution::default();
+
+        let a = TypeList::new().with_row(row("..r"));
+        let b = TypeList::from(vec![var_type("x")]);
+
+        assert_eq!(subst.unify_lists(&a, &b), Ok(()));
+        assert_eq!(subst.unify(&var_type("x"), &integer_type()), Ok(()));
+        assert_eq!(subst.row("..r"), Some(&TypeList::from(vec![integer_type()])));
+    }
+}
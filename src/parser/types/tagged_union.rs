@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use crate::parser::signature_parser::TypedSignature;
+
+use super::{typ::Type, typelist::TypeList, typ, var_types};
+
+pub type Unions = HashMap<String, Union>;
+
+/// A tagged union declared with `union shape [circle[num] rect[num num] empty]`.
+/// Values are pointers to the tag, followed by the payload of the variant,
+/// everything occupying 8 bytes
+#[derive(Clone, Debug)]
+pub struct Union {
+    pub name: String,
+
+    /// The type variables of a generic union, `'a` for `option['a]`
+    pub params: Vec<String>,
+
+    /// The index of a variant is its tag
+    pub variants: Vec<(String, Vec<Type>)>
+}
+
+impl Union {
+    pub fn new(name: String, params: Vec<String>, variants: Vec<(String, Vec<Type>)>) -> Self {
+        Union { name, params, variants }
+    }
+
+    pub fn typ(&self) -> Type {
+        typ(&self.name, var_types(&self.params))
+    }
+
+    pub fn tag(&self, variant: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|(name, _)| name == variant)
+    }
+
+    pub fn payload(&self, tag: usize) -> &Vec<Type> {
+        &self.variants[tag].1
+    }
+
+    /// Size of a value of the given variant
+    pub fn size(&self, tag: usize) -> usize {
+        (self.payload(tag).len() + 1) * 8
+    }
+
+    /// The constructor words, one per variant. Just like for records, the
+    /// first value of the payload is on top: `rect 2 3`
+    pub fn words(&self) -> Vec<(String, TypedSignature)> {
+        self.variants
+            .iter()
+            .map(|(name, payload)| (
+                name.clone(),
+                TypedSignature::new(
+                    TypeList::from(payload.iter().rev().cloned().collect::<Vec<Type>>()),
+                    TypeList::from(vec![self.typ()])
+                )
+            ))
+            .collect()
+    }
+}
@@ -1,166 +1,122 @@
-use std::{fmt::{Display, Formatter}, sync::{Mutex, Arc}};
+use std::{fmt::{Display, Formatter}, collections::HashMap};
 
-use ariadne::{Color, Fmt};
+use super::{typelist::{TypeList, RowVar}, substitution::Substitution, func_type, FUNC_TYPE_NAME};
 
-use super::{typelist::TypeList, type_env::TypeEnv, typ, var_type_raw};
-
-pub type VarContent = Arc<Mutex<Option<Type>>>;
-
-#[derive(Clone, Debug)]
+/// Variables only have a name, what is known about them is kept in a `Substitution`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     Kind(String, TypeList),
-    Variable(String, VarContent)
-}
-
-impl PartialEq for Type {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Kind(a_name, a_vars), Self::Kind(b_name, b_vars)) => 
-                a_name == b_name && a_vars == b_vars,
-
-            // Theoretically it's valid to assume that if two variables
-            // with the same name are unified, it's a bug if their contents
-            // are not equal; therefore we may only compare the variable names
-            (Self::Variable(a_name, _), Self::Variable(b_name, _)) => 
-                a_name == b_name,
-
-            _ => false,
-        }
-    }
+    Variable(String)
 }
 
 impl Type {
-    pub fn unify<'a>(&'a self, other: &'a Self) -> Result<(), String> {
+    /// Replaces the variables by the given types, e.g. the parameters of a generic type
+    pub fn substitute(&self, subst: &Substitution) -> Type {
         use Type::*;
 
-        match (self, other) {
-            // Unify types
-            (Kind(a, types_a), Kind(b, types_b)) if a == b && types_a.len() == types_b.len() =>
-                types_a
-                    .vec()
-                    .iter()
-                    .zip(types_b.vec())
-                    .try_for_each(|(a, b)| a.unify(b)),
-            
-            (Variable(vname, content), other) | (other, Variable(vname, content)) => {
-                {
-                    if let Some(inner) = content.lock().unwrap().as_ref() {
-                        return inner.unify(other)
-                    }
-                }
-                
-                if self == other {
-                    Ok(())
-                }
-                else if other.occurs(vname) {
-                    Err(format!(
-                        "Occurs Check: Type {} contains typevar {}",
-                        other.fg(Color::Cyan),
-                        vname.fg(Color::Cyan),
-                    ))
-                }
-                else {
-                    *content.lock().unwrap() = Some(other.clone());
-                    Ok(())
-                }
-            }
- 
-            (_, _) => Err(format!(
-                "Type Mismatch: {} and {}",
-                self.fg(Color::Cyan),
-                other.fg(Color::Cyan)
-            ))
+        match self {
+            Kind(name, types) => 
+                Kind(name.clone(), types.substitute(subst)),
+
+            Variable(name) => subst
+                .var(name)
+                .cloned()
+                .unwrap_or_else(|| self.clone())
         }
     }
 
-    pub fn refresh_vars(&self, env: &mut TypeEnv) -> Type {
+    /// Collects the types the variables of this type stand for in `actual`
+    pub fn match_vars(&self, actual: &Type, vars: &mut HashMap<String, Type>) {
         use Type::*;
 
-        match self {
-            Kind(a, types) =>
-                Kind(a.clone(), types.refresh_vars(env)),
+        match (self, actual) {
+            (Variable(name), actual) => {
+                vars.entry(name.clone()).or_insert_with(|| actual.clone());
+            },
 
-            Variable(name, content) => 
-                env.new_var(name.clone(), content.lock().unwrap().clone()),
+            (Kind(a, types_a), Kind(b, types_b)) if a == b => types_a
+                .iter()
+                .zip(types_b.iter())
+                .for_each(|(a, b)| a.match_vars(b, vars)),
+
+            _ => ()
         }
     }
 
-    pub fn clear_vars(&self) {
+    pub fn occurs(&self, var: &str) -> bool {
         use Type::*;
-
+    
         match self {
-            Kind(_, types) => types.clear_vars(),
-
-            Variable(_, content) => { 
-                *content.lock().unwrap() = None;
-            },
+            Kind(_, types) => types.occurs(var),
+            
+            Variable(name) => var == name
         }
     }
 
-    pub fn has_bound_vars(&self) -> bool {
+    /// Adds the names of the type and row variables, each one once
+    pub fn free_vars(&self, vars: &mut Vec<String>) {
         use Type::*;
 
         match self {
-            Kind(_, types) =>
-                types.has_bound_vars(),
+            Kind(_, types) => types.free_vars(vars),
 
-            Variable(_, content) => 
-                content.lock().unwrap().is_some(),
+            Variable(name) if !vars.contains(name) => vars.push(name.clone()),
+
+            Variable(_) => ()
         }
     }
 
-    pub fn occurs(&self, var: &String) -> bool {
-        use Type::*;
-    
+    /// The argument and return lists of a function type
+    pub fn function_effect(&self) -> Option<(&TypeList, &TypeList)> {
         match self {
-            Kind(_, types) => types.occurs(var),
-            
-            Variable(name, content) => 
-                var == name || content.lock()
-                    .unwrap()
-                    .as_ref()
-                    .map_or(false, |inner| inner.occurs(var))
+            Type::Kind(name, types) if name == FUNC_TYPE_NAME => match &types.vec()[..] {
+                [Type::Kind(_, args), Type::Kind(_, rets), ..] => Some((args, rets)),
+
+                _ => None
+            },
+
+            _ => None
         }
     }
 
-    pub fn concretize(&self) -> Type {
-        use Type::*;
-    
+    /// The constraints of a function type, e.g. `Num['a]`
+    pub fn function_constraints(&self) -> TypeList {
         match self {
-            Kind(name, types) => 
-                Kind(name.clone(), types.concretize()),
-            
-            Variable(_, content) => {
-                match content.lock().unwrap().as_ref() {
-                    Some(inner) => inner.concretize(),
+            Type::Kind(name, types) if name == FUNC_TYPE_NAME => match &types.vec()[..] {
+                [_, _, Type::Kind(_, constraints)] => constraints.clone(),
+
+                _ => TypeList::new()
+            },
 
-                    None => self.clone()
-                }
-            }
+            _ => TypeList::new()
         }
     }
 
-    pub fn extract_function(&self) -> Option<(TypeList, TypeList)> {
-        let args = Arc::new(Mutex::new(None));
-        let res = Arc::new(Mutex::new(None));
+    pub(super) fn has_row_vars(&self) -> bool {
+        self.function_effect()
+            .is_some_and(|(args, rets)| args.row().is_some() || rets.row().is_some())
+    }
 
-        let typ = typ("fun", vec![
-            var_type_raw("_a", args.clone()),
-            var_type_raw("_b", res.clone())
-        ]);
+    // A function without row variables leaves the rest of the stack as it
+    // is, so ( a -- b ) is the same as ( ..r a -- ..r b ) for a fresh ..r
+    pub(super) fn row_extended(&self) -> Option<Type> {
+        match self.function_effect() {
+            Some((args, rets)) if args.row().is_none() && rets.row().is_none() => {
+                let row = RowVar::fresh();
+
+                Some(func_type(
+                    args.clone().with_row(row.clone()),
+                    rets.clone().with_row(row)
+                ))
+            },
 
-        let unified = typ.unify(self);
-        if unified.is_err() {
-            return None;
+            _ => None
         }
-            
-        let a = args.lock().unwrap().clone();
-        let b = res.lock().unwrap().clone();
+    }
 
-        if let (Some(Type::Kind(_, arguments)), Some(Type::Kind(_, returns))) = (a, b) {
-            Some((arguments, returns))
-        }
-        else { None }
+    pub fn extract_function(&self) -> Option<(TypeList, TypeList)> {
+        self.function_effect()
+            .map(|(args, rets)| (args.clone(), rets.clone()))
     }
 }
 
@@ -171,16 +127,17 @@ impl Display for Type {
         match self {
             Kind(name, types) => {
                 let type_str = types
-                    .vec()
-                    .iter()
-                    .map(|t| format!("[{t}]"))
+                    .row()
+                    .map(|row| format!("[{}]", row.name()))
+                    .into_iter()
+                    .chain(types.vec().iter().map(|t| format!("[{t}]")))
                     .collect::<Vec<String>>()
                     .join("");
 
                 write!(f, "{name}{type_str}")
             },
                
-            Variable(name, _) => write!(f, "'{name}")
+            Variable(name) => write!(f, "'{name}")
         }
     }
 }
@@ -1,14 +1,41 @@
 use std::{collections::HashMap, sync::{Mutex, Arc}};
 
-use super::{typelist::TypeList, typ::Type};
+use crate::lexer::token::Token;
 
-pub type TypeBindings = HashMap<String, Type>;
+use super::{typelist::{TypeList, RowVar}, typ::Type, record::Records, tagged_union::{Unions, Union}, alias::Aliases, class::{Classes, Instances, Instance}, substitution::Substitution, scheme::Scheme, BUILTIN_TYPE_NAMES, fresh_name};
+
+pub type TypeBindings = HashMap<String, Scheme>;
+
+/// The constraints of the calls, with the token of the call
+pub type WantedConstraints = Arc<Mutex<Vec<(Type, Token)>>>;
 
 #[derive(Clone, Debug, Default)]
 pub struct TypeEnv {
-    pub var_counter: u32,
     pub stack: TypeList,
-    pub bindings: TypeBindings
+    pub bindings: TypeBindings,
+    pub records: Records,
+    pub unions: Unions,
+    pub aliases: Aliases,
+    pub classes: Classes,
+    pub instances: Instances,
+
+    /// Grows while typechecking, nested scopes hand theirs back
+    pub substitution: Substitution,
+
+    /// Collected until the end of the function, where they are solved.
+    /// Nested scopes like lists and match arms share them
+    pub wanted: WantedConstraints,
+
+    /// The constraints of the signature of the enclosing function
+    pub given: TypeList,
+
+    /// Set while inferring the signature of a function, values
+    /// missing on the stack then become its arguments
+    pub inferred_args: Option<TypeList>,
+
+    /// The returns of the signature of the enclosing function, 
+    /// which `try` may return early with
+    pub function_returns: Option<TypeList>
 }
 
 impl TypeEnv {
@@ -23,10 +50,145 @@ impl TypeEnv {
         Self::new(&self.bindings)
     }
 
-    pub fn new_var(&mut self, name: String, val: Option<Type>) -> Type {
-        let name = format!("{name}{}", self.var_counter);
-        self.var_counter += 1;
+    pub fn new_var(&self, name: &str) -> Type {
+        Type::Variable(fresh_name(name))
+    }
+
+    pub fn new_row(&self, name: &str) -> RowVar {
+        RowVar(fresh_name(name))
+    }
+
+    /// The type with everything that is known about its variables
+    pub fn apply(&self, typ: &Type) -> Type {
+        typ.substitute(&self.substitution)
+    }
+
+    pub fn apply_list(&self, list: &TypeList) -> TypeList {
+        list.substitute(&self.substitution)
+    }
+
+    pub fn unify(&mut self, a: &Type, b: &Type) -> Result<(), String> {
+        self.substitution.unify(a, b)
+    }
+
+    pub fn unify_lists(&mut self, a: &TypeList, b: &TypeList) -> Result<(), String> {
+        self.substitution.unify_lists(a, b)
+    }
+
+    /// The variables which may still be bound by the enclosing function,
+    /// so bindings cannot be generic over them
+    pub fn free_vars(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+
+        self.apply_list(&self.stack).free_vars(&mut vars);
+        self.apply_list(&self.given).free_vars(&mut vars);
+
+        for list in self.inferred_args.iter().chain(self.function_returns.iter()) {
+            self.apply_list(list).free_vars(&mut vars);
+        }
+
+        for scheme in self.bindings.values() {
+            scheme.substitute(&self.substitution).free_vars(&mut vars);
+        }
+
+        vars
+    }
+
+    /// Makes sure there are at least n values on the stack, if a
+    /// signature is being inferred
+    pub fn infer_missing(&mut self, n: usize) {
+        if self.inferred_args.is_none() || self.stack.len() >= n {
+            return
+        }
+
+        let missing: Vec<Type> = (self.stack.len()..n)
+            .map(|_| self.new_var("t"))
+            .collect();
+
+        let mut stack = TypeList::from(missing.clone());
+        stack.extend(self.stack.clone());
+        self.stack = stack;
+
+        // They are below all arguments found so far
+        if let Some(args) = &mut self.inferred_args {
+            let mut inferred = TypeList::from(missing);
+            inferred.extend(args.clone());
+            *args = inferred;
+        }
+    }
+
+    pub fn type_exists(&self, name: &str) -> bool {
+        BUILTIN_TYPE_NAMES.contains(&name) 
+            || self.records.contains_key(name) 
+            || self.unions.contains_key(name)
+            || self.aliases.contains_key(name)
+    }
+
+    /// Replaces expansions of aliases by the alias, so that errors show
+    /// `strings` instead of `list[str]`. Aliases of types without any 
+    /// arguments, like `type n num`, are left alone
+    pub fn resugar(&self, typ: &Type) -> Type {
+        let typ = &self.apply(typ);
+
+        let mut aliases: Vec<_> = self.aliases
+            .values()
+            .filter(|alias| matches!(&alias.typ, Type::Kind(_, inner) if !inner.is_empty()))
+            .collect();
+
+        aliases.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for alias in aliases {
+            if let Some(args) = alias.matches(typ) {
+                let args: Vec<Type> = args
+                    .iter()
+                    .map(|arg| self.resugar(arg))
+                    .collect();
+
+                return Type::Kind(alias.name.clone(), TypeList::from(args))
+            }
+        }
+
+        match typ {
+            Type::Kind(name, inner) => Type::Kind(name.clone(), self.resugar_list(inner)),
+
+            Type::Variable(..) => typ.clone()
+        }
+    }
+
+    pub fn resugar_list(&self, types: &TypeList) -> TypeList {
+        let types = &self.apply_list(types);
+
+        let resugared = TypeList::from(types
+            .iter()
+            .map(|typ| self.resugar(typ))
+            .collect::<Vec<Type>>());
+
+        match types.row() {
+            Some(row) => resugared.with_row(row.clone()),
+
+            None => resugared
+        }
+    }
+
+    /// The instance satisfying the constraint, e.g. `Show[num]`
+    pub fn instance(&self, constraint: &Type) -> Option<&Instance> {
+        let constraint = self.apply(constraint);
+
+        self.instances
+            .iter()
+            .find(|instance| instance.constraint() == constraint)
+    }
+
+    pub fn want(&self, constraints: &TypeList, token: &Token) {
+        self.wanted
+            .lock()
+            .unwrap()
+            .extend(constraints.iter().map(|constraint| (constraint.clone(), token.clone())));
+    }
 
-        Type::Variable(name, Arc::new(Mutex::new(val)))
+    pub fn union_of_variant(&self, variant: &str) -> Option<&Union> {
+        self.unions
+            .values()
+            .find(|union| union.tag(variant).is_some())
     }
 }
\ No newline at end of file
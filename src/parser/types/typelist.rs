@@ -1,16 +1,44 @@
 use std::fmt::Display;
 use core::ops::{Deref, DerefMut};
 
-use super::{typ::Type, type_env::TypeEnv};
+use super::{typ::Type, substitution::Substitution, fresh_name};
 
-#[derive(Debug, PartialEq, Clone, Default)]
-pub struct TypeList(Vec<Type>);
+/// Stands for the rest of the stack below the elements of a TypeList.
+/// The name always starts with two dots, e.g. `..s`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RowVar(pub String);
+
+impl RowVar {
+    /// Rows created while unifying do not have access to a TypeEnv
+    pub fn fresh() -> Self {
+        RowVar(fresh_name("..r"))
+    }
+
+    pub fn name(&self) -> &String {
+        &self.0
+    }
+}
+
+/// The types on a stack, the last one is the top. If there is a row variable,
+/// it stands for the (unknown) rest of the stack below
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct TypeList(Vec<Type>, Option<RowVar>);
 
 impl TypeList {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn with_row(mut self, row: RowVar) -> Self {
+        self.1 = Some(row);
+        self
+    }
+
+    pub fn row(&self) -> Option<&RowVar> {
+        self.1.as_ref()
+    }
+
+    /// Only the types above the row variable
     pub fn vec(&self) -> &Vec<Type> {
         &self.0
     }
@@ -24,7 +52,8 @@ impl TypeList {
     }
 
     pub fn clear(&mut self) {
-        self.0.clear()
+        self.0.clear();
+        self.1 = None;
     }
 
     pub fn extend(&mut self, other: Self) {
@@ -43,31 +72,37 @@ impl TypeList {
         self.0[(self.len() - n)..].to_owned()
     }
 
-    pub fn clear_vars(&self) { 
-        self.0.iter().for_each(|typ| typ.clear_vars())
-    }
+    /// A bound row variable is replaced by the types it stands for
+    pub fn substitute(&self, subst: &Substitution) -> TypeList {
+        let types = self.0
+            .iter()
+            .map(|typ| typ.substitute(subst));
 
-    pub fn refresh_vars(&self, env: &mut TypeEnv) -> TypeList {
-        TypeList(self.0.clone()
-            .into_iter()
-            .map(|typ| typ.refresh_vars(env))
-            .collect())
-    }
+        match self.1.as_ref().and_then(|row| subst.row(row.name())) {
+            Some(inner) => {
+                let mut list = inner.clone();
+                list.0.extend(types);
+                list
+            },
 
-    pub fn has_bound_vars(&self) -> bool {
-        self.0.iter().any(|typ| typ.has_bound_vars())
+            None => TypeList(types.collect(), self.1.clone())
+        }
     }
 
-    pub fn occurs(&self, var: &String) -> bool {
+    pub fn occurs(&self, var: &str) -> bool {
         self.0.iter().any(|t| t.occurs(var))
+            || self.1.as_ref().is_some_and(|row| row.0 == var)
     }
-    
-    pub fn concretize(&self) -> TypeList {
-        TypeList(self.0
-            .clone()
-            .into_iter()
-            .map(|typ| typ.concretize())
-            .collect())
+
+    /// Adds the names of the type and row variables, each one once
+    pub fn free_vars(&self, vars: &mut Vec<String>) {
+        if let Some(row) = &self.1 {
+            if !vars.contains(&row.0) {
+                vars.push(row.0.clone());
+            }
+        }
+
+        self.0.iter().for_each(|typ| typ.free_vars(vars));
     }
 }
 
@@ -83,7 +118,7 @@ impl IntoIterator for TypeList {
 
 impl From<Vec<Type>> for TypeList {
     fn from(value: Vec<Type>) -> Self {
-        TypeList(value)
+        TypeList(value, None)
     }
 }
 
@@ -103,16 +138,17 @@ impl DerefMut for TypeList {
 
 impl Display for TypeList {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.vec().is_empty(){
+        if self.vec().is_empty() && self.1.is_none() {
             return write!(f, "<nothing>");
         }
 
-        let msg = self.vec()
+        let msg = self.1
             .iter()
-            .map(|t| t.to_string())
+            .map(|row| row.0.clone())
+            .chain(self.vec().iter().map(|t| t.to_string()))
             .collect::<Vec<String>>()
             .join(" ");
 
         write!(f, "{msg}")
     }
-}
\ No newline at end of file
+}
@@ -3,7 +3,7 @@ use std::sync::{Mutex, Arc};
 use nu_ansi_term::{Style, Color};
 use reedline::{Highlighter, StyledText};
 
-use crate::lexer::{lex, token::Token};
+use crate::lexer::{lex_raw, token::Token};
 
 use super::symbols::Symbols;
 
@@ -17,6 +17,7 @@ lazy_static! {
     static ref GET_STYLE: Style = Style::new().fg(Color::LightBlue).bold();
     static ref STRING_STYLE: Style = Style::new().fg(Color::Green);
     static ref NUMBER_STYLE: Style = Style::new().fg(Color::Magenta);
+    static ref COMMENT_STYLE: Style = Style::new().fg(Color::DarkGray).italic();
 }
 
 pub struct EzHighlighter {
@@ -28,11 +29,38 @@ impl EzHighlighter {
         Self { symbols }
     }
 
+    // Lists, tuples, function bodies, match arms and methods are highlighted token 
+    // by token, the delimiters and signatures in between get the default style
+    fn flatten_tokens(tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .flat_map(|token| match token {
+                Token::List { value, .. } | Token::Tuple { value, .. } => Self::flatten_tokens(value),
+
+                Token::Function { body, .. } => Self::flatten_tokens(body),
+
+                Token::Match { arms, .. } => arms
+                    .into_iter()
+                    .flat_map(|(_, body)| Self::flatten_tokens(body))
+                    .collect(),
+
+                Token::Instance { methods, .. } => methods
+                    .into_iter()
+                    .flat_map(|(_, body)| Self::flatten_tokens(body))
+                    .collect(),
+
+                Token::Newline => Vec::new(),
+
+                _ => vec![token]
+            })
+            .collect()
+    }
+
     fn token_to_style(&self, tok: &Token) -> Style {
         match tok {
-            Token::Number { .. } => *NUMBER_STYLE,
+            Token::Number { .. } | Token::Integer { .. } | Token::Bool { .. } => *NUMBER_STYLE,
             
-            Token::Quote { .. } => *STRING_STYLE,
+            Token::Quote { .. } | Token::InterpolatedQuote { .. } => *STRING_STYLE,
 
             Token::Ident { value, .. } | Token::GetIdent { value, .. } 
                 if !self.symbols.lock().unwrap().contains(value) => *UNKNOWN_STYLE,
@@ -40,6 +68,8 @@ impl EzHighlighter {
             Token::GetIdent { .. } => *GET_STYLE,
             
             Token::Assigment { .. } => *ASSIGMENT_STYLE,
+
+            Token::Comment { .. } => *COMMENT_STYLE,
     
             _ => *DEFAULT_STYLE,
         }
@@ -58,8 +88,8 @@ impl Highlighter for EzHighlighter {
         }
 
         // Handle case where we fail to parse the line
-        let mut tokens = if let Ok(tokens) = lex(line.to_owned()){
-            tokens
+        let mut tokens = if let Ok(tokens) = lex_raw(line.to_owned()){
+            Self::flatten_tokens(tokens)
         }
         else {
             text.push((*DEFAULT_STYLE, line.to_owned()));
@@ -67,11 +97,9 @@ impl Highlighter for EzHighlighter {
             return text
         };
 
-        tokens.sort_by(|a, b| 
-            a.range().start.cmp(&b.range().start));
+        tokens.sort_by_key(|tok| tok.span().start());
 
-        // This will 100% fail for some unicode input..
-        // but I don't care at the moment
+        // i is a byte offset into the line
         let mut i = 0;
 
         loop {
@@ -89,7 +117,7 @@ impl Highlighter for EzHighlighter {
                 break;
             };
 
-            let range = token.range();
+            let range = token.span().byte_range(line);
             if i == range.start {
                 // Handle if we actually found a token
                 let style = self.token_to_style(token);
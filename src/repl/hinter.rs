@@ -42,7 +42,7 @@ impl Hinter for EzHinter {
                 .first()
                 .map(|completion|
                     completion.chars()
-                        .skip(word.len())
+                        .skip(word.chars().count())
                         .collect()
                 )
                 .unwrap_or_default()
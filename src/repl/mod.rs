@@ -2,15 +2,16 @@ mod hinter;
 mod symbols;
 mod completer;
 mod highlighter;
+mod validator;
 
 use std::sync::{Mutex, Arc};
 
-use reedline::{DefaultPrompt, Reedline, Signal, Prompt, DefaultPromptSegment, DefaultValidator, Keybindings, KeyModifiers, ReedlineEvent, KeyCode, default_emacs_keybindings, Emacs, ColumnarMenu, ReedlineMenu};
+use reedline::{DefaultPrompt, Reedline, Signal, Prompt, DefaultPromptSegment, Keybindings, KeyModifiers, ReedlineEvent, KeyCode, default_emacs_keybindings, Emacs, ColumnarMenu, ReedlineMenu};
 use yansi::{Color, Style};
 
-use crate::{codegen::jit::Jit, config::Config};
+use crate::{codegen::jit::Jit, config::Config, source::{add_source, replace_source, SourceId}};
 
-use self::{hinter::EzHinter, symbols::Symbols, completer::EzCompleter, highlighter::EzHighlighter};
+use self::{hinter::EzHinter, symbols::Symbols, completer::EzCompleter, highlighter::EzHighlighter, validator::EzValidator};
 
 lazy_static! {
     static ref BOLD: Style = Style::new(Color::Fixed(7)).bold();
@@ -74,7 +75,10 @@ pub struct Repl {
     current_symbols: Arc<Mutex<Symbols>>,
     config: Config,
     prompt: Box<dyn Prompt>,
-    silent: bool
+    silent: bool,
+
+    /// Holds the line being run, replaced with every new one
+    source: SourceId
 }
 
 impl Repl {
@@ -87,7 +91,7 @@ impl Repl {
         let hinter = EzHinter::new(current_symbols.clone());
         let completer = EzCompleter::new(current_symbols.clone());
         let highlighter = EzHighlighter::new(current_symbols.clone());
-        let validator = DefaultValidator {};
+        let validator = EzValidator;
         let edit_mode = Emacs::new(Self::default_keybindings());
 
         let completion_menu = ColumnarMenu::default().with_name("completion_menu");
@@ -108,7 +112,8 @@ impl Repl {
             current_symbols,
             config,
             prompt,
-            silent: false
+            silent: false,
+            source: add_source("<repl>", String::new())
         }
     }
 
@@ -139,7 +144,9 @@ impl Repl {
     }
 
     fn run(&mut self, buffer: String) {
-        match self.jit.run_saving(buffer.clone(), &self.config.debug_config) {
+        replace_source(self.source, buffer);
+
+        match self.jit.run_saving(self.source, &self.config.debug_config) {
             Ok(_) => {
                 if !self.silent {
                     let state = self.jit.jit_state();
@@ -151,7 +158,7 @@ impl Repl {
             },
 
             Err(err) => 
-                err.report(buffer),
+                err.report(),
         }
     }
 
@@ -173,7 +180,7 @@ impl Repl {
                     return
                 }
 
-                let mut config = &mut self.config.debug_config;
+                let config = &mut self.config.debug_config;
 
                 if args.contains(&"tokens"){
                     config.emit_tokens = !config.emit_tokens;
@@ -0,0 +1,19 @@
+use reedline::{Validator, ValidationResult};
+
+use crate::lexer::is_unfinished;
+
+/// Keeps the editor in multi-line mode until every `[`, `{`, `(`
+/// and `"` is closed
+pub struct EzValidator;
+
+impl Validator for EzValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        // REPL commands are always single lines
+        if line.starts_with('.') || !is_unfinished(line.to_owned()) {
+            ValidationResult::Complete
+        }
+        else {
+            ValidationResult::Incomplete
+        }
+    }
+}
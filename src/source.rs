@@ -0,0 +1,193 @@
+use std::{ops::Range, sync::Mutex, fmt::{Debug, Display}, collections::HashMap};
+
+use ariadne::{Cache, Source};
+
+lazy_static! {
+    static ref SOURCES: Mutex<SourceDb> = Mutex::new(SourceDb::default());
+}
+
+/// Identifies a source registered in the source database
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+impl SourceId {
+    /// For code which is never reported on, e.g. while highlighting
+    pub const ANONYMOUS: SourceId = SourceId(usize::MAX);
+}
+
+/// Spans always use char offsets, just like chumsky and ariadne do.
+/// Use `Span::byte_range` for slicing strs
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub source: SourceId,
+
+    pub range: Range<usize>
+}
+
+impl Span {
+    pub fn new(source: SourceId, range: Range<usize>) -> Self {
+        Span { source, range }
+    }
+
+    pub fn start(&self) -> usize {
+        self.range.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.range.end
+    }
+
+    /// Converts the char offsets into byte offsets, which are needed
+    /// for slicing the given source
+    pub fn byte_range(&self, src: &str) -> Range<usize> {
+        byte_offset(src, self.start())..byte_offset(src, self.end())
+    }
+}
+
+fn byte_offset(src: &str, char_offset: usize) -> usize {
+    src.char_indices()
+        .nth(char_offset)
+        .map(|(i, _)| i)
+        .unwrap_or(src.len())
+}
+
+impl ariadne::Span for Span {
+    type SourceId = SourceId;
+
+    fn source(&self) -> &SourceId {
+        &self.source
+    }
+
+    fn start(&self) -> usize {
+        self.range.start
+    }
+
+    fn end(&self) -> usize {
+        self.range.end
+    }
+}
+
+struct SourceFile {
+    name: String,
+
+    content: String,
+
+    source: Source
+}
+
+/// Holds every piece of code that was loaded - files, REPL inputs and
+/// the ez snippets of the stdlib - so errors can point into any of them
+#[derive(Default)]
+pub struct SourceDb {
+    files: Vec<SourceFile>,
+
+    /// Sources which are added again, like the stdlib snippets of 
+    /// every new `Jit`, get the id of the first one
+    ids: HashMap<(String, String), SourceId>
+}
+
+impl SourceDb {
+    pub fn add(&mut self, name: String, content: String) -> SourceId {
+        let key = (name, content);
+
+        if let Some(id) = self.ids.get(&key) {
+            return *id
+        }
+
+        let (name, content) = key.clone();
+
+        self.files.push(SourceFile {
+            name,
+            source: Source::from(content.clone()),
+            content
+        });
+
+        let id = SourceId(self.files.len() - 1);
+        self.ids.insert(key, id);
+
+        id
+    }
+
+    /// Replaces the content of a source, e.g. the REPL reuses its source
+    /// for every line, since a line is never reported on once it ran
+    pub fn replace(&mut self, id: SourceId, content: String) {
+        let Some(file) = self.files.get_mut(id.0) else {
+            return
+        };
+
+        // Sources which may change are never handed out again by `add`
+        self.ids.remove(&(file.name.clone(), file.content.clone()));
+
+        file.source = Source::from(content.clone());
+        file.content = content;
+    }
+
+    pub fn content(&self, id: SourceId) -> Option<&str> {
+        self.files
+            .get(id.0)
+            .map(|file| file.content.as_str())
+    }
+}
+
+impl Cache<SourceId> for &SourceDb {
+    fn fetch(&mut self, id: &SourceId) -> Result<&Source, Box<dyn Debug + '_>> {
+        self.files
+            .get(id.0)
+            .map(|file| &file.source)
+            .ok_or_else(|| Box::new(format!("Unknown source {id:?}")) as Box<dyn Debug>)
+    }
+
+    fn display<'a>(&self, id: &'a SourceId) -> Option<Box<dyn Display + 'a>> {
+        self.files
+            .get(id.0)
+            .map(|file| Box::new(file.name.clone()) as Box<dyn Display>)
+    }
+}
+
+pub fn add_source<N: ToString>(name: N, content: String) -> SourceId {
+    SOURCES
+        .lock()
+        .unwrap()
+        .add(name.to_string(), content)
+}
+
+pub fn replace_source(id: SourceId, content: String) {
+    SOURCES
+        .lock()
+        .unwrap()
+        .replace(id, content)
+}
+
+pub fn source_content(id: SourceId) -> String {
+    SOURCES
+        .lock()
+        .unwrap()
+        .content(id)
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn with_sources<R, F: FnOnce(&SourceDb) -> R>(f: F) -> R {
+    f(&SOURCES.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_reused() {
+        let mut sources = SourceDb::default();
+
+        let stdlib = sources.add("<stdlib>/dup".into(), "a a".into());
+        assert_eq!(sources.add("<stdlib>/dup".into(), "a a".into()), stdlib);
+
+        let repl = sources.add("<repl>".into(), String::new());
+        sources.replace(repl, "1 2".into());
+        sources.replace(repl, "3".into());
+
+        assert_eq!(sources.content(repl), Some("3"));
+        assert_ne!(sources.add("<repl>".into(), String::new()), repl);
+        assert_eq!(sources.files.len(), 3);
+    }
+}
@@ -1,7 +1,7 @@
 use cranelift::prelude::FunctionBuilder;
 use cranelift_module::{Module, Linkage};
 
-use crate::{parser::{signature_parser::TypedSignature, node::Node, parse, types::type_env::TypeEnv}, codegen::{function_translator::{FunctionTranslator, FunctionOptions}, codegen_module::CodeGenModule}, error::Error, match_nodes, lexer::lex};
+use crate::{parser::{signature_parser::TypedSignature, node::Node, parse_program, types::type_env::TypeEnv}, codegen::{function_translator::{FunctionTranslator, FunctionOptions}, codegen_module::CodeGenModule}, error::Error, match_nodes, lexer::lex, source::add_source};
 
 pub trait CodeTransformation<M: Module> {
     fn try_apply<'b>(
@@ -23,8 +23,11 @@ pub trait EzFun<M: Module> {
         false
     }
 
+    /// Inlines the function, `sig` contains the types the function 
+    /// is actually called with
     fn try_apply_inline<'b>(
         &self,
+        _sig: &TypedSignature,
         _nodes: &mut Vec<Node>,
         _translator: &mut FunctionTranslator<'b, M>,
         _builder: &mut FunctionBuilder
@@ -33,6 +36,17 @@ pub trait EzFun<M: Module> {
     }
 }
 
+pub fn apply_inline_body<'b, M: Module, F>(
+    sig: &TypedSignature,
+    translator: &mut FunctionTranslator<'b, M>,
+    builder: &mut FunctionBuilder,
+    body: F
+) -> Result<(), Error> 
+    where F: FnOnce(&mut FunctionTranslator<'b, M>, &mut FunctionBuilder, &TypedSignature) -> Result<(), Error>
+{
+    body(translator, builder, sig)
+}
+
 pub struct FuncCodeTransformation<M> {
     pub inner: Box<dyn EzFun<M>>
 }
@@ -45,11 +59,13 @@ impl<M: Module> CodeTransformation<M> for FuncCodeTransformation<M> {
         builder: &mut FunctionBuilder
     ) -> Result<bool, Error> {
         match_nodes!(
-            nodes: [Node::Call { name, .. }, ..] if name == self.inner.name() => {
+            nodes: [Node::Call { name, arguments, returns, .. }, ..] if name == self.inner.name() => {
                 nodes.remove(0);
 
                 if self.inner.should_inline() {
-                    return self.inner.try_apply_inline(nodes, translator, builder);
+                    let sig = TypedSignature::new(arguments.clone(), returns.clone());
+
+                    return self.inner.try_apply_inline(&sig, nodes, translator, builder);
                 }
 
                 translator.ins_call(name, self.inner.signature().arguments().len(), builder)?;
@@ -114,20 +130,41 @@ impl UserFun {
     }
 
     fn new_raw(name: &str, sig: &str, src: &str, tenv: &mut TypeEnv, inline: bool) -> Result<Self, Error> {
-        let tokens = lex(src.to_string())?;
-        let nodes = parse(tokens, tenv)?;
+        let sig: TypedSignature = sig.parse()?;
+        let (instance, fresh) = sig.instantiate(tenv);
+
+        tenv.stack = instance.arguments().clone();
+        tenv.given = instance.constraints().clone();
+
+        // Inlined functions have no frame of their own, which try could return from
+        tenv.function_returns = (!inline).then(|| instance.returns().clone());
+
+        let source = add_source(format!("<stdlib>/{name}"), src.to_string());
+        let tokens = lex(source)?;
+
+        // The body uses the variables of the signature again, so that they can be 
+        // replaced by the types of a call when inlining
+        let nodes = parse_program(tokens, tenv)?
+            .iter()
+            .map(|node| node.substitute(&fresh.inverse()))
+            .collect();
 
         Ok(Self {
             name: name.to_string(),
             inline,
             src: nodes,
-            sig: sig.parse()?
+            sig
         })
     }
 }
 
 impl<M: Module> EzFun<M> for UserFun {
     fn init(&self, codegen: &mut CodeGenModule<M>) -> Result<(), Error> {
+        // The body is inserted at every call site, generic ones could not be compiled on their own
+        if self.inline {
+            return Ok(())
+        }
+
         FunctionTranslator::new(codegen)
             .with_signature(self.sig.clone())
             .with_body(self.src.clone())?
@@ -148,12 +185,20 @@ impl<M: Module> EzFun<M> for UserFun {
 
     fn try_apply_inline<'b>(
             &self,
+            sig: &TypedSignature,
             _nodes: &mut Vec<Node>,
             translator: &mut FunctionTranslator<'b, M>,
             builder: &mut FunctionBuilder
         ) -> Result<bool, Error> {
-        
-        translator.translate_nodes(self.src.clone(), builder)?;
+        // A generic body is translated with the types of this call, so
+        // that the methods of type classes find their instance
+        let vars = self.sig.match_vars(sig);
+        let body = self.src
+            .iter()
+            .map(|node| node.substitute(&vars))
+            .collect();
+
+        translator.translate_nodes(body, builder)?;
 
         Ok(true)
     }
@@ -0,0 +1,40 @@
+use cranelift::prelude::*;
+use cranelift_module::Module;
+
+use crate::{parser::{node::Node, types::class::Instance}, codegen::function_translator::FunctionTranslator, error::Error, match_nodes};
+
+use super::functions::CodeTransformation;
+
+/// Inlines the methods of an instance wherever they are called with its type
+pub struct InstanceMethods {
+    instance: Instance
+}
+
+impl InstanceMethods {
+    pub fn new(instance: Instance) -> Self {
+        Self { instance }
+    }
+}
+
+impl<M: Module> CodeTransformation<M> for InstanceMethods {
+    fn try_apply<'b>(
+        &self,
+        nodes: &mut Vec<Node>,
+        translator: &mut FunctionTranslator<'b, M>,
+        builder: &mut FunctionBuilder
+    ) -> Result<bool, Error> {
+        let constraint = self.instance.constraint();
+
+        match_nodes!(
+            nodes: [Node::Call { name, constraints, .. }, ..] 
+                if self.instance.method(name).is_some() 
+                    && constraints.contains(&constraint) => 
+            {
+                nodes.remove(0);
+
+                let body = self.instance.method(name).unwrap().clone();
+                translator.translate_nodes(body, builder)?;
+            }
+        )
+    }
+}
@@ -2,7 +2,7 @@ use std::rc::Rc;
 
 use cranelift_module::Module;
 
-use crate::{parser::types::type_env::{TypeBindings, TypeEnv}, codegen::codegen_module::CodeGenModule, error::Error};
+use crate::{parser::{types::{type_env::{TypeBindings, TypeEnv}, record::Records, tagged_union::Unions, alias::Aliases, class::{Classes, Instances}}, parse_program}, codegen::codegen_module::CodeGenModule, error::Error, lexer::lex, source::add_source};
 
 use super::functions::{CodeTransformation, EzFun, FuncCodeTransformation};
 
@@ -12,6 +12,16 @@ pub type Functions<M> = Vec<Box<dyn EzFun<M>>>;
 pub struct Library<M: Module> {
     pub bindings: TypeBindings,
 
+    pub records: Records,
+
+    pub unions: Unions,
+
+    pub aliases: Aliases,
+
+    pub classes: Classes,
+
+    pub instances: Instances,
+
     pub functions: Functions<M>,
 
     pub transformations: Transformations<M>
@@ -22,6 +32,16 @@ impl<M: Module + 'static> Library<M> {
         Self {
             bindings: TypeBindings::new(),
 
+            records: Records::new(),
+
+            unions: Unions::new(),
+
+            aliases: Aliases::new(),
+
+            classes: Classes::new(),
+
+            instances: Instances::new(),
+
             functions: Functions::new(),
 
             transformations: Transformations::new()
@@ -29,9 +49,38 @@ impl<M: Module + 'static> Library<M> {
     }
 
 
+    /// Declares the records, unions, type aliases, classes and instances of the ez source
+    pub fn declare(&mut self, name: &str, src: &str) -> Result<(), Error> {
+        let source = add_source(format!("<stdlib>/{name}"), src.to_string());
+
+        let mut tenv = self.type_env();
+        parse_program(lex(source)?, &mut tenv)?;
+
+        self.bindings = tenv.bindings;
+        self.records = tenv.records;
+        self.unions = tenv.unions;
+        self.aliases = tenv.aliases;
+        self.classes = tenv.classes;
+        self.instances = tenv.instances;
+
+        Ok(())
+    }
+
     pub fn init_codegen(self, codegen: &mut CodeGenModule<M>) -> Result<(), Error> {
         codegen.transformations.extend(self.transformations);
 
+        for record in self.records.into_values() {
+            codegen.declare_record(record);
+        }
+
+        for union in self.unions.into_values() {
+            codegen.declare_union(union);
+        }
+
+        for instance in self.instances {
+            codegen.declare_instance(instance);
+        }
+
         for func in self.functions {
             func.init(codegen)?;
 
@@ -43,6 +92,13 @@ impl<M: Module + 'static> Library<M> {
     }
 
     pub fn type_env(&self) -> TypeEnv {
-        TypeEnv::new(&self.bindings)
+        TypeEnv {
+            records: self.records.clone(),
+            unions: self.unions.clone(),
+            aliases: self.aliases.clone(),
+            classes: self.classes.clone(),
+            instances: self.instances.clone(),
+            ..TypeEnv::new(&self.bindings)
+        }
     }
 }
\ No newline at end of file
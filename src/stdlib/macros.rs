@@ -16,6 +16,10 @@ macro_rules! match_nodes {
 #[macro_export]
 macro_rules! library {
     (functions { $($func:tt)* } transformations { $($transf:tt)* }) => {
+        $crate::library!(types "" functions { $($func)* } instances "" transformations { $($transf)* })
+    };
+
+    (types $types:literal functions { $($func:tt)* } instances $instances:literal transformations { $($transf:tt)* }) => {
         {
             use cranelift_module::Module;
             use std::rc::Rc;
@@ -35,9 +39,21 @@ macro_rules! library {
 
             let mut library = Library::new();
 
+            // The functions may already use the types
+            library.declare("types", $types).unwrap_or_else(|err| {
+                err.report();
+                panic!("Invalid stdlib types")
+            });
+
             __gen_transforms!(library, $($transf)*);
             __gen_funcs!(library, $($func)*);
 
+            // The methods of the instances are implemented by the functions
+            library.declare("instances", $instances).unwrap_or_else(|err| {
+                err.report();
+                panic!("Invalid stdlib instances")
+            });
+
             library
         }
     };
@@ -71,7 +87,7 @@ macro_rules! __gen_transforms {
 #[macro_export]
 macro_rules! __register {
     ($library:ident, $func:ident, $name:ident, $sig:ident) => {
-        $library.bindings.insert($name, $sig);
+        $library.bindings.insert($name, $crate::parser::types::scheme::Scheme::closed($sig));
         $library.functions.push(Box::new($func));
     };
 }
@@ -123,6 +139,50 @@ macro_rules! __gen_funcs {
         __gen_funcs!($library, $($tail)*)
     };
 
+    ($library:ident, inline fn $name:ident ($sig:literal) $blk:expr; $($tail:tt)*) => {
+        #[allow(non_camel_case_types)]
+        struct $name;
+
+        impl<M: Module> EzFun<M> for $name {
+            fn init(&self, _codegen: &mut $crate::codegen::codegen_module::CodeGenModule<M>) -> Result<(), Error> {
+                // Nothing to generate, the body is inserted at every call site
+                Ok(())
+            }
+        
+            // Words which are Rust keywords are raw identifiers, e.g. `r#if`
+            #[inline]
+            fn name(&self) -> &str {
+                stringify!($name).trim_start_matches("r#")
+            }
+        
+            #[inline]
+            fn signature(&self) -> TypedSignature {
+                format!("({})", $sig).parse().unwrap()
+            }
+
+            fn should_inline(&self) -> bool { true }
+
+            fn try_apply_inline<'b>(
+                &self,
+                sig: &TypedSignature,
+                _nodes: &mut Vec<Node>,
+                translator: &mut FunctionTranslator<'b, M>,
+                builder: &mut FunctionBuilder
+            ) -> Result<bool, Error> {
+                apply_inline_body(sig, translator, builder, $blk)?;
+
+                Ok(true)
+            }
+        }
+
+        let func = $name {};
+        let name = <$name as EzFun<M>>::name(&func).to_string();
+        let sig: Type = <$name as EzFun<M>>::signature(&func).into();
+
+        __register!($library, func, name, sig);
+        __gen_funcs!($library, $($tail)*)
+    };
+
     ($library:ident, #[inline] ez $($content:tt)*) => {
         __gen_funcs!($library, true, ez_int $($content)*)
     };
@@ -137,17 +197,19 @@ macro_rules! __gen_funcs {
         let src = $src;
 
         let mut tenv = $library.type_env();
-        tenv.stack = sig.parse::<TypedSignature>()
-            .unwrap()
-            .arguments()
-            .clone();
 
         let func = if $inline {
-            UserFun::new_inline(name, sig.clone().as_str(), src, &mut tenv).unwrap()
+            UserFun::new_inline(name, sig.clone().as_str(), src, &mut tenv)
         }
         else {
-            UserFun::new(name, sig.clone().as_str(), src, &mut tenv).unwrap()
+            UserFun::new(name, sig.clone().as_str(), src, &mut tenv)
         };
+
+        // The snippet is in the source database, so the error can point into it
+        let func = func.unwrap_or_else(|err| {
+            err.report();
+            panic!("Invalid stdlib function {name}")
+        });
         
         
         let name = <UserFun as EzFun<M>>::name(&func).to_string();
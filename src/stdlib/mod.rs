@@ -1,23 +1,55 @@
 pub mod functions;
 pub mod library;
 pub mod macros;
+pub mod records;
+pub mod unions;
+pub mod instances;
+pub mod numbers;
 
 use cranelift::prelude::InstBuilder;
 use cranelift::prelude::*;
 use cranelift_module::Module;
 
-use crate::library;
+use crate::{library, codegen::function_translator::{FunctionTranslator, LoopControl}, error::Error, parser::signature_parser::TypedSignature};
 
 use self::library::Library;
 
 pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
     library! {
+        types r#"
+            union option['a] [some['a] none]
+            union result['a]['e] [ok['a] err['e]]
+
+            class Num['a] [
+                add: ( 'a 'a -- 'a ) 
+                sub: ( 'a 'a -- 'a ) 
+                mul: ( 'a 'a -- 'a ) 
+                div: ( 'a 'a -- 'a )
+            ]
+
+            class Eq['a] [
+                eq: ( 'a 'a -- bool ) 
+                neq: ( 'a 'a -- bool )
+            ]
+
+            class Ord['a] [
+                lt: ( 'a 'a -- bool ) 
+                gt: ( 'a 'a -- bool ) 
+                le: ( 'a 'a -- bool ) 
+                ge: ( 'a 'a -- bool )
+            ]
+
+            class Show['a] [
+                show: ( 'a -- str )
+            ]
+        "#
+
         functions {
             native fn malloc("ci64 -- pointer");
             native fn puts("cstr -- ci32");
             native fn exit("ci32 -- ");
 
-            mezzaine fn add("num num -- num")|trans, builder|{
+            mezzaine fn fadd("num num -- num")|trans, builder|{
                 let a = trans.pop_value();
                 let b = trans.pop_value();
                 
@@ -28,7 +60,7 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
                 Ok(())
             };
 
-            mezzaine fn sub("num num -- num")|trans, builder|{
+            mezzaine fn fsub("num num -- num")|trans, builder|{
                 let a = trans.pop_value();
                 let b = trans.pop_value();
                 
@@ -39,7 +71,7 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
                 Ok(())
             };
 
-            mezzaine fn mul("num num -- num")|trans, builder|{
+            mezzaine fn fmul("num num -- num")|trans, builder|{
                 let a = trans.pop_value();
                 let b = trans.pop_value();
                 
@@ -50,7 +82,7 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
                 Ok(())
             };
 
-            mezzaine fn div("num num -- num")|trans, builder|{
+            mezzaine fn fdiv("num num -- num")|trans, builder|{
                 let a = trans.pop_value();
                 let b = trans.pop_value();
                 
@@ -61,6 +93,352 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
                 Ok(())
             };
 
+            mezzaine fn feq("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::Equal, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn fneq("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::NotEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn flt("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::LessThan, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn fgt("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::GreaterThan, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn fle("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::LessThanOrEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn fge("num num -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().fcmp(FloatCC::GreaterThanOrEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn iadd("int int -- int")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().iadd(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn isub("int int -- int")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().isub(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn imul("int int -- int")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().imul(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn idiv("int int -- int")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().sdiv(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn imod("int int -- int")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().srem(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn ieq("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::Equal, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn ineq("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::NotEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn ilt("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::SignedLessThan, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn igt("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::SignedGreaterThan, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn ile("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::SignedLessThanOrEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn ige("int int -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn num("int -- num")|trans, builder|{
+                let a = trans.pop_value();
+                
+                let res = builder.ins().fcvt_from_sint(types::F64, a);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn int("num -- int")|trans, builder|{
+                let a = trans.pop_value();
+                
+                // Rounds towards zero, saturates instead of trapping for NaN and
+                // values out of range
+                let res = builder.ins().fcvt_to_sint_sat(types::I64, a);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn not("bool -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                
+                // Bools are either 0 or 1, so flipping the lowest bit is enough
+                let res = builder.ins().bxor_imm(a, Imm64::from(1));
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn and("bool bool -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().band(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn or("bool bool -- bool")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+                
+                let res = builder.ins().bor(a, b);
+
+                trans.push_value(res);
+                
+                Ok(())
+            };
+
+            mezzaine fn concat("str str -- str")|trans, builder|{
+                let a = trans.pop_value();
+                let b = trans.pop_value();
+
+                let res = trans.concat_strings(vec![a, b], builder)?;
+
+                trans.push_value(res);
+
+                Ok(())
+            };
+
+            mezzaine fn num2str("num -- str")|trans, builder|{
+                numbers::translate_num2str(trans, builder)
+            };
+
+            mezzaine fn int2str("int -- str")|trans, builder|{
+                let value = trans.pop_value();
+                let flags = MemFlags::trusted();
+
+                // The digits are computed from the absolute value, which is even correct
+                // for the smallest int as long as it's treated as unsigned
+                let is_negative = builder.ins().icmp_imm(IntCC::SignedLessThan, value, 0);
+                let negated = builder.ins().ineg(value);
+                let abs = builder.ins().select(is_negative, negated, value);
+
+                // Count the digits
+                let count_block = builder.create_block();
+                builder.append_block_param(count_block, types::I64); // What's left of the value
+                builder.append_block_param(count_block, types::I64); // Digits so far
+
+                let alloc_block = builder.create_block();
+                builder.append_block_param(alloc_block, types::I64); // Digits
+
+                let one = builder.ins().iconst(types::I64, 1);
+                builder.ins().jump(count_block, &[abs, one]);
+
+                builder.switch_to_block(count_block);
+                let rest = builder.block_params(count_block)[0];
+                let digits = builder.block_params(count_block)[1];
+                let next_rest = builder.ins().udiv_imm(rest, 10);
+                let next_digits = builder.ins().iadd_imm(digits, 1);
+                builder.ins().brif(next_rest, count_block, &[next_rest, next_digits], alloc_block, &[digits]);
+
+                // Allocate the str, its content is written back to front
+                builder.switch_to_block(alloc_block);
+                let digits = builder.block_params(alloc_block)[0];
+                let sign_len = builder.ins().uextend(types::I64, is_negative);
+                let length = builder.ins().iadd(digits, sign_len);
+
+                let size = builder.ins().iadd_imm(length, 9);
+                trans.push_value(size);
+                trans.ins_call("malloc", 1, builder)?;
+                let str = trans.pop_value();
+
+                builder.ins().store(flags, length, str, 0);
+
+                // str + length + 8 is right behind the content
+                let end = builder.ins().iadd(str, length);
+                let zero = builder.ins().iconst(types::I8, 0);
+                builder.ins().store(flags, zero, end, 8);
+
+                // Gets overwritten by the first digit for positive values
+                let minus = builder.ins().iconst(types::I8, '-' as i64);
+                builder.ins().store(flags, minus, str, 8);
+
+                let write_block = builder.create_block();
+                builder.append_block_param(write_block, types::I64); // What's left of the value
+                builder.append_block_param(write_block, types::I64); // Where to write the digit - 7
+
+                let done_block = builder.create_block();
+
+                builder.ins().jump(write_block, &[abs, end]);
+
+                builder.switch_to_block(write_block);
+                let rest = builder.block_params(write_block)[0];
+                let position = builder.block_params(write_block)[1];
+                let digit = builder.ins().urem_imm(rest, 10);
+                let char = builder.ins().iadd_imm(digit, '0' as i64);
+                let char = builder.ins().ireduce(types::I8, char);
+                builder.ins().store(flags, char, position, 7);
+
+                let next_rest = builder.ins().udiv_imm(rest, 10);
+                let next_position = builder.ins().iadd_imm(position, -1);
+                builder.ins().brif(next_rest, write_block, &[next_rest, next_position], done_block, &[]);
+
+                builder.switch_to_block(done_block);
+                trans.push_value(str);
+
+                Ok(())
+            };
+
+            mezzaine fn bool2str("bool -- str")|trans, builder|{
+                let value = trans.pop_value();
+
+                let true_str = trans.build_quote("true", builder)?;
+                let false_str = trans.build_quote("false", builder)?;
+
+                let res = builder.ins().select(value, true_str, false_str);
+
+                trans.push_value(res);
+
+                Ok(())
+            };
+
             mezzaine fn cstr("str -- cstr")|trans, builder|{
                 let top = trans.pop_value();
                 
@@ -72,19 +450,244 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
                 Ok(())
             };
 
-            #[inline]
-            ez fn pop("ci32 --") r#"
-                a:
-            "#;
+            inline fn len("list['a] -- int")|trans, builder, _sig|{
+                let list = trans.pop_value();
 
-            #[inline]
-            ez fn swap("num num -- num num") r#"
-                b a b: a:
-            "#;
+                // The first 8 bytes of a list store its length
+                let length = builder.ins().load(types::I64, MemFlags::trusted(), list, 0);
+
+                trans.push_value(length);
+
+                Ok(())
+            };
+
+            inline fn nth("int list['a] -- 'a")|trans, builder, sig|{
+                let list = trans.pop_value();
+                let index = trans.pop_value();
+
+                let length = builder.ins().load(types::I64, MemFlags::trusted(), list, 0);
+                let out_of_bounds = builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, index, length);
+                builder.ins().trapnz(out_of_bounds, TrapCode::HeapOutOfBounds);
+
+                // Every element occupies 8 bytes, right after the length
+                let offset = builder.ins().imul_imm(index, 8);
+                let address = builder.ins().iadd(list, offset);
+
+                let typ = sig.returns()[0].clone().into();
+                let elem = builder.ins().load(typ, MemFlags::trusted(), address, 8);
+
+                trans.push_value(elem);
+
+                Ok(())
+            };
+
+            // The success variants some and ok have the tag 0
+
+            inline fn issome("option['a] -- bool")|trans, builder, _sig|{
+                let option = trans.pop_value();
+
+                let tag = builder.ins().load(types::I64, MemFlags::trusted(), option, 0);
+                let res = builder.ins().icmp_imm(IntCC::Equal, tag, 0);
+
+                trans.push_value(res);
+
+                Ok(())
+            };
+
+            inline fn isok("result['a]['e] -- bool")|trans, builder, _sig|{
+                let result = trans.pop_value();
+
+                let tag = builder.ins().load(types::I64, MemFlags::trusted(), result, 0);
+                let res = builder.ins().icmp_imm(IntCC::Equal, tag, 0);
+
+                trans.push_value(res);
+
+                Ok(())
+            };
+
+            inline fn someor("'a option['a] -- 'a")|trans, builder, sig|{
+                let option = trans.pop_value();
+                let default = trans.pop_value();
+
+                let typ = sig.returns()[0].clone().into();
+                let value = unwrap_or(option, default, typ, builder);
+
+                trans.push_value(value);
+
+                Ok(())
+            };
+
+            inline fn okor("'a result['a]['e] -- 'a")|trans, builder, sig|{
+                let result = trans.pop_value();
+                let default = trans.pop_value();
+
+                let typ = sig.returns()[0].clone().into();
+                let value = unwrap_or(result, default, typ, builder);
+
+                trans.push_value(value);
+
+                Ok(())
+            };
+
+            // The stack shuffling words only move SSA values around, so they
+            // work for any type and never generate a call
+
+            inline fn dup("'a -- 'a 'a")|trans, _builder, _sig|{
+                let a = trans.pop_value();
+
+                trans.push_value(a);
+                trans.push_value(a);
+
+                Ok(())
+            };
+
+            inline fn drop("'a --") drop_top;
+
+            // The old name of drop
+            inline fn pop("'a --") drop_top;
+
+            inline fn swap("'a 'b -- 'b 'a")|trans, _builder, _sig|{
+                let b = trans.pop_value();
+                let a = trans.pop_value();
+
+                trans.push_value(b);
+                trans.push_value(a);
+
+                Ok(())
+            };
+
+            inline fn over("'a 'b -- 'a 'b 'a")|trans, _builder, _sig|{
+                let b = trans.pop_value();
+                let a = trans.pop_value();
+
+                trans.push_value(a);
+                trans.push_value(b);
+                trans.push_value(a);
+
+                Ok(())
+            };
+
+            inline fn rot("'a 'b 'c -- 'b 'c 'a")|trans, _builder, _sig|{
+                let c = trans.pop_value();
+                let b = trans.pop_value();
+                let a = trans.pop_value();
+
+                trans.push_value(b);
+                trans.push_value(c);
+                trans.push_value(a);
+
+                Ok(())
+            };
+
+            inline fn nip("'a 'b -- 'b")|trans, _builder, _sig|{
+                let b = trans.pop_value();
+                trans.pop_value();
+
+                trans.push_value(b);
+
+                Ok(())
+            };
+
+            inline fn tuck("'a 'b -- 'b 'a 'b")|trans, _builder, _sig|{
+                let b = trans.pop_value();
+                let a = trans.pop_value();
+
+                trans.push_value(b);
+                trans.push_value(a);
+                trans.push_value(b);
+
+                Ok(())
+            };
+
+            // The combinators call the function values on the stack, whose
+            // types give the signature of the indirect call
+
+            inline fn call("..a fun[arg[..a]][ret[..b]] -- ..b")|trans, builder, sig|{
+                trans.ins_call_indirect(&sig.arguments()[0], builder)
+            };
+
+            inline fn dip("..a 'x fun[arg[..a]][ret[..b]] -- ..b 'x")|trans, builder, sig|{
+                let fun = trans.pop_value();
+                let x = trans.pop_value();
+
+                trans.push_value(fun);
+                trans.ins_call_indirect(&sig.arguments()[1], builder)?;
+                trans.push_value(x);
+
+                Ok(())
+            };
+
+            inline fn keep("..a 'x fun[arg[..a 'x]][ret[..b]] -- ..b 'x")|trans, builder, sig|{
+                let fun = trans.pop_value();
+                let x = trans.pop_value();
+
+                trans.push_value(x);
+                trans.push_value(fun);
+                trans.ins_call_indirect(&sig.arguments()[1], builder)?;
+                trans.push_value(x);
+
+                Ok(())
+            };
+
+            // `bi {p} {q} x` applies p to x and then q to x
+            inline fn bi("..a 'x fun[arg[..b 'x]][ret[..c]] fun[arg[..a 'x]][ret[..b]] -- ..c")|trans, builder, sig|{
+                let first = trans.pop_value();
+                let second = trans.pop_value();
+                let x = trans.pop_value();
+
+                trans.push_value(x);
+                trans.push_value(first);
+                trans.ins_call_indirect(&sig.arguments()[2], builder)?;
+
+                trans.push_value(x);
+                trans.push_value(second);
+                trans.ins_call_indirect(&sig.arguments()[1], builder)
+            };
+
+            // The branches and the bodies of loops are function values as well. Both branches 
+            // have the same stack effect and a loop body leaves the values it takes, which 
+            // may be below, e.g. `ifelse { fadd 1 } { } true 2` or `times { fmul 2 } 10i 1`
+
+            inline fn ifelse("..a bool fun[arg[..a]][ret[..b]] fun[arg[..a]][ret[..b]] -- ..b")|trans, builder, sig|{
+                let then = trans.pop_value();
+                let otherwise = trans.pop_value();
+                let condition = trans.pop_value();
+
+                trans.ins_branch(condition, (then, &sig.arguments()[2]), Some((otherwise, &sig.arguments()[1])), builder)
+            };
+
+            inline fn r#if("..a bool fun[arg[..a]][ret[..a]] -- ..a")|trans, builder, sig|{
+                let then = trans.pop_value();
+                let condition = trans.pop_value();
+
+                trans.ins_branch(condition, (then, &sig.arguments()[1]), None, builder)
+            };
+
+            inline fn times("..a int fun[arg[..a]][ret[..a]] -- ..a")|trans, builder, sig|{
+                let body = trans.pop_value();
+                let count = trans.pop_value();
+
+                trans.ins_loop(LoopControl::Times(count), (body, &sig.arguments()[1]), builder)
+            };
+
+            inline fn each("..a list['e] fun[arg[..a 'e]][ret[..a]] -- ..a")|trans, builder, sig|{
+                let body = trans.pop_value();
+                let list = trans.pop_value();
+
+                trans.ins_loop(LoopControl::Each(list, &sig.arguments()[0]), (body, &sig.arguments()[1]), builder)
+            };
+
+            // `while { condition } { body }`
+            inline fn r#while("..a fun[arg[..a]][ret[..a]] fun[arg[..a]][ret[..a bool]] -- ..a")|trans, builder, sig|{
+                let condition = trans.pop_value();
+                let body = trans.pop_value();
+
+                trans.ins_loop(LoopControl::While(condition, &sig.arguments()[1]), (body, &sig.arguments()[0]), builder)
+            };
 
             #[inline]
-            ez fn print("str -- ") r#"
-                pop puts cstr
+            ez fn print("Show['a] => 'a -- ") r#"
+                drop puts cstr show
             "#;
 
             #[inline]
@@ -93,7 +696,38 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
             "#;
         }
 
+        instances r#"
+            instance Num[num] [add: { fadd } sub: { fsub } mul: { fmul } div: { fdiv }]
+            instance Num[int] [add: { iadd } sub: { isub } mul: { imul } div: { idiv }]
+
+            instance Eq[num] [eq: { feq } neq: { fneq }]
+            instance Eq[int] [eq: { ieq } neq: { ineq }]
+
+            instance Ord[num] [lt: { flt } gt: { fgt } le: { fle } ge: { fge }]
+            instance Ord[int] [lt: { ilt } gt: { igt } le: { ile } ge: { ige }]
+
+            instance Show[str] [show: { }]
+            instance Show[num] [show: { num2str }]
+            instance Show[int] [show: { int2str }]
+            instance Show[bool] [show: { bool2str }]
+        "#
+
         transformations {
+            // The parser made sure a tuple is on top, the elements are the returns
+            transform unpack: [Node::Call { name, returns, .. }, ..] if name == "unpack" => |nodes, trans, builder|{
+                nodes.remove(0);
+
+                let tuple = trans.pop_value();
+
+                // The first element is stored first and ends up on top
+                for (i, typ) in returns.iter().enumerate() {
+                    let offset = (returns.len() - 1 - i) * 8;
+                    let elem = builder.ins().load(typ.clone().into(), MemFlags::trusted(), tuple, offset as i32);
+
+                    trans.push_value(elem);
+                }
+            };
+
             // We implicitly assume the last elem is the jitstate
             // Yes bad things will happen if this is not the case...
             transform __save: [Node::Call { name, .. }, ..] if name == "__save" => |nodes, trans, builder|{
@@ -146,3 +780,28 @@ pub fn create_stdlib<M: Module + 'static>() -> Library<M> {
         }
     }
 }
+
+fn drop_top<M: Module>(trans: &mut FunctionTranslator<M>, _builder: &mut FunctionBuilder, _sig: &TypedSignature) -> Result<(), Error> {
+    trans.pop_value();
+
+    Ok(())
+}
+
+/// The payload of a some or an ok, otherwise the default
+fn unwrap_or(value: Value, default: Value, typ: Type, builder: &mut FunctionBuilder) -> Value {
+    let tag = builder.ins().load(types::I64, MemFlags::trusted(), value, 0);
+
+    let success_block = builder.create_block();
+    let merge_block = builder.create_block();
+    builder.append_block_param(merge_block, typ);
+
+    // Failures have no payload to load
+    builder.ins().brif(tag, merge_block, &[default], success_block, &[]);
+
+    builder.switch_to_block(success_block);
+    let payload = builder.ins().load(typ, MemFlags::trusted(), value, 8);
+    builder.ins().jump(merge_block, &[payload]);
+
+    builder.switch_to_block(merge_block);
+    builder.block_params(merge_block)[0]
+}
\ No newline at end of file
@@ -0,0 +1,434 @@
+use cranelift::prelude::*;
+use cranelift_module::Module;
+
+use crate::{codegen::function_translator::FunctionTranslator, error::Error};
+
+/// The significant digits of a formatted number, like `%.15g` of printf
+const DIGITS: i64 = 15;
+
+/// The largest power of ten which is exactly representable as a num
+const EXACT_POWER: i64 = 22;
+
+/// The variables of the generated code
+#[derive(Clone, Copy)]
+struct Vars {
+    /// Where the next char is written
+    position: Variable,
+
+    /// The value which is formatted, without its sign
+    abs: Variable,
+
+    /// The power of ten which is not larger than the value
+    power: Variable,
+
+    /// The decimal exponent of the value
+    exp: Variable,
+
+    /// The value scaled to its significant digits
+    scaled: Variable,
+
+    /// The significant digits which are not written yet
+    mantissa: Variable,
+
+    /// The number of significant digits, without the trailing zeros
+    digits: Variable,
+
+    /// The power of ten of the next digit of the mantissa
+    divisor: Variable,
+
+    /// The counter of a loop
+    counter: Variable,
+
+    /// The remaining power of ten the value is scaled by
+    shift: Variable
+}
+
+/// Formats the num on top like `%.15g` does. This is done by hand, as `strfromd`
+/// only exists in glibc and snprintf is variadic, which Cranelift does not support
+pub fn translate_num2str<M: Module>(trans: &mut FunctionTranslator<M>, builder: &mut FunctionBuilder) -> Result<(), Error> {
+    let value = trans.pop_value();
+    let flags = MemFlags::trusted();
+
+    // The longest ones take 22 bytes, e.g. -1.23456789012345e-308 or -0.000123456789012345
+    let size = builder.ins().iconst(types::I64, 8 + 32);
+    trans.push_value(size);
+    trans.ins_call("malloc", 1, builder)?;
+    let str = trans.pop_value();
+
+    let vars = declare_vars(builder);
+    let content = builder.ins().iadd_imm(str, 8);
+    builder.def_var(vars.position, content);
+
+    // The sign bit is set for -0 too
+    let bits = builder.ins().bitcast(types::I64, MemFlags::new(), value);
+    let is_negative = builder.ins().icmp_imm(IntCC::SignedLessThan, bits, 0);
+    if_then(builder, is_negative, |b| write_str(b, vars, "-"));
+
+    let abs = builder.ins().fabs(value);
+    builder.def_var(vars.abs, abs);
+
+    let is_nan = builder.ins().fcmp(FloatCC::Unordered, value, value);
+    let infinity = builder.ins().f64const(f64::INFINITY);
+    let is_infinite = builder.ins().fcmp(FloatCC::Equal, abs, infinity);
+    let zero = builder.ins().f64const(0.0);
+    let is_zero = builder.ins().fcmp(FloatCC::Equal, abs, zero);
+
+    if_else(builder, is_nan, |b| write_str(b, vars, "nan"), |b| {
+        if_else(b, is_infinite, |b| write_str(b, vars, "inf"), |b| {
+            if_else(b, is_zero, |b| write_str(b, vars, "0"), |b| write_finite(b, vars))
+        })
+    });
+
+    let position = builder.use_var(vars.position);
+    let zero = builder.ins().iconst(types::I8, 0);
+    builder.ins().store(flags, zero, position, 0);
+
+    let length = builder.ins().isub(position, content);
+    builder.ins().store(flags, length, str, 0);
+
+    trans.push_value(str);
+
+    Ok(())
+}
+
+fn declare_vars(builder: &mut FunctionBuilder) -> Vars {
+    let mut next = 0;
+
+    let mut var = |typ: types::Type| {
+        let var = Variable::new(next);
+        builder.declare_var(var, typ);
+        next += 1;
+        var
+    };
+
+    Vars {
+        position: var(types::I64),
+        abs: var(types::F64),
+        power: var(types::F64),
+        exp: var(types::I64),
+        scaled: var(types::F64),
+        mantissa: var(types::I64),
+        digits: var(types::I64),
+        divisor: var(types::I64),
+        counter: var(types::I64),
+        shift: var(types::I64)
+    }
+}
+
+/// Writes a finite value which is not zero, in the fixed notation for exponents
+/// from -4 to 14 and in the scientific notation else
+fn write_finite(b: &mut FunctionBuilder, vars: Vars) {
+    let abs = b.use_var(vars.abs);
+
+    // The largest power of ten not larger than the value. Above 1e22 the powers are not
+    // exact, which the scaled value is corrected for
+    let one = b.ins().f64const(1.0);
+    let zero = b.ins().iconst(types::I64, 0);
+    b.def_var(vars.power, one);
+    b.def_var(vars.exp, zero);
+
+    while_loop(b, |b| {
+        let power = b.use_var(vars.power);
+        let next = float_op(b, power, 10.0, |b, x, y| b.ins().fmul(x, y));
+        b.ins().fcmp(FloatCC::GreaterThanOrEqual, abs, next)
+    }, |b| {
+        let power = b.use_var(vars.power);
+        let next = float_op(b, power, 10.0, |b, x, y| b.ins().fmul(x, y));
+        b.def_var(vars.power, next);
+        increment(b, vars.exp, 1);
+    });
+
+    while_loop(b, |b| {
+        let power = b.use_var(vars.power);
+        b.ins().fcmp(FloatCC::LessThan, abs, power)
+    }, |b| {
+        let power = b.use_var(vars.power);
+        let next = float_op(b, power, 10.0, |b, x, y| b.ins().fdiv(x, y));
+        b.def_var(vars.power, next);
+        increment(b, vars.exp, -1);
+    });
+
+    // The value times 10^(DIGITS - 1 - exp), the last step uses an exact power
+    // of ten, so that it is rounded only once for the usual exponents
+    let exp = b.use_var(vars.exp);
+    let shift = b.ins().irsub_imm(exp, DIGITS - 1);
+    b.def_var(vars.shift, shift);
+    b.def_var(vars.scaled, abs);
+
+    while_loop(b, |b| {
+        let shift = b.use_var(vars.shift);
+        b.ins().icmp_imm(IntCC::SignedGreaterThan, shift, EXACT_POWER)
+    }, |b| {
+        let scaled = b.use_var(vars.scaled);
+        let scaled = float_op(b, scaled, 1e22, |b, x, y| b.ins().fmul(x, y));
+        b.def_var(vars.scaled, scaled);
+        increment(b, vars.shift, -EXACT_POWER);
+    });
+
+    while_loop(b, |b| {
+        let shift = b.use_var(vars.shift);
+        b.ins().icmp_imm(IntCC::SignedLessThan, shift, -EXACT_POWER)
+    }, |b| {
+        let scaled = b.use_var(vars.scaled);
+        let scaled = float_op(b, scaled, 1e22, |b, x, y| b.ins().fdiv(x, y));
+        b.def_var(vars.scaled, scaled);
+        increment(b, vars.shift, EXACT_POWER);
+    });
+
+    let shift = b.use_var(vars.shift);
+    let shift_abs = b.ins().iabs(shift);
+    b.def_var(vars.counter, shift_abs);
+    b.def_var(vars.power, one);
+
+    repeat(b, vars.counter, |b| {
+        let power = b.use_var(vars.power);
+        let next = float_op(b, power, 10.0, |b, x, y| b.ins().fmul(x, y));
+        b.def_var(vars.power, next);
+    });
+
+    let scaled = b.use_var(vars.scaled);
+    let power = b.use_var(vars.power);
+    let multiplied = b.ins().fmul(scaled, power);
+    let divided = b.ins().fdiv(scaled, power);
+    let is_up = b.ins().icmp_imm(IntCC::SignedGreaterThanOrEqual, shift, 0);
+    let scaled = b.ins().select(is_up, multiplied, divided);
+
+    let rounded = float_op(b, scaled, 0.5, |b, x, y| b.ins().fadd(x, y));
+    let mantissa = b.ins().fcvt_to_sint_sat(types::I64, rounded);
+    b.def_var(vars.mantissa, mantissa);
+
+    // Rounding may carry into another digit, e.g. for 9.9999999999999999
+    let too_long = b.ins().icmp_imm(IntCC::SignedGreaterThanOrEqual, mantissa, 10_i64.pow(DIGITS as u32));
+    if_then(b, too_long, |b| {
+        let mantissa = b.use_var(vars.mantissa);
+        let mantissa = b.ins().iadd_imm(mantissa, 5);
+        let mantissa = b.ins().sdiv_imm(mantissa, 10);
+        b.def_var(vars.mantissa, mantissa);
+        increment(b, vars.exp, 1);
+    });
+
+    // The power for large exponents may be a bit too small
+    let mantissa = b.use_var(vars.mantissa);
+    let too_short = b.ins().icmp_imm(IntCC::SignedLessThan, mantissa, 10_i64.pow(DIGITS as u32 - 1));
+    if_then(b, too_short, |b| {
+        let mantissa = b.use_var(vars.mantissa);
+        let mantissa = b.ins().imul_imm(mantissa, 10);
+        b.def_var(vars.mantissa, mantissa);
+        increment(b, vars.exp, -1);
+    });
+
+    // Trailing zeros are left out
+    let digits = b.ins().iconst(types::I64, DIGITS);
+    b.def_var(vars.digits, digits);
+
+    while_loop(b, |b| {
+        let mantissa = b.use_var(vars.mantissa);
+        let rest = b.ins().srem_imm(mantissa, 10);
+        b.ins().icmp_imm(IntCC::Equal, rest, 0)
+    }, |b| {
+        let mantissa = b.use_var(vars.mantissa);
+        let mantissa = b.ins().sdiv_imm(mantissa, 10);
+        b.def_var(vars.mantissa, mantissa);
+        increment(b, vars.digits, -1);
+    });
+
+    // The digits are written from the front, starting at the divisor of the first one
+    let digits = b.use_var(vars.digits);
+    let one = b.ins().iconst(types::I64, 1);
+    let counter = b.ins().iadd_imm(digits, -1);
+    b.def_var(vars.divisor, one);
+    b.def_var(vars.counter, counter);
+
+    repeat(b, vars.counter, |b| {
+        let divisor = b.use_var(vars.divisor);
+        let divisor = b.ins().imul_imm(divisor, 10);
+        b.def_var(vars.divisor, divisor);
+    });
+
+    let exp = b.use_var(vars.exp);
+    let below = b.ins().icmp_imm(IntCC::SignedLessThan, exp, -4);
+    let above = b.ins().icmp_imm(IntCC::SignedGreaterThanOrEqual, exp, DIGITS);
+    let is_scientific = b.ins().bor(below, above);
+    let is_negative = b.ins().icmp_imm(IntCC::SignedLessThan, exp, 0);
+
+    if_else(b, is_scientific, |b| write_scientific(b, vars), |b| {
+        if_else(b, is_negative, |b| write_fraction(b, vars), |b| write_fixed(b, vars))
+    });
+}
+
+/// E.g. 1.5e+20 or 1e-05
+fn write_scientific(b: &mut FunctionBuilder, vars: Vars) {
+    write_digit(b, vars);
+
+    let digits = b.use_var(vars.digits);
+    let counter = b.ins().iadd_imm(digits, -1);
+    b.def_var(vars.counter, counter);
+
+    if_then(b, counter, |b| write_str(b, vars, "."));
+    repeat(b, vars.counter, |b| write_digit(b, vars));
+
+    write_str(b, vars, "e");
+
+    let exp = b.use_var(vars.exp);
+    let is_negative = b.ins().icmp_imm(IntCC::SignedLessThan, exp, 0);
+    if_else(b, is_negative, |b| write_str(b, vars, "-"), |b| write_str(b, vars, "+"));
+
+    // At least two digits
+    let exp = b.ins().iabs(exp);
+    let hundreds = b.ins().icmp_imm(IntCC::SignedGreaterThanOrEqual, exp, 100);
+    if_then(b, hundreds, |b| {
+        let digit = b.ins().sdiv_imm(exp, 100);
+        write_char(b, vars, digit);
+    });
+
+    let tens = b.ins().sdiv_imm(exp, 10);
+    let tens = b.ins().srem_imm(tens, 10);
+    write_char(b, vars, tens);
+
+    let ones = b.ins().srem_imm(exp, 10);
+    write_char(b, vars, ones);
+}
+
+/// E.g. 0.00015 for exponents from -4 to -1
+fn write_fraction(b: &mut FunctionBuilder, vars: Vars) {
+    write_str(b, vars, "0.");
+
+    let exp = b.use_var(vars.exp);
+    let zeros = b.ins().irsub_imm(exp, -1);
+    b.def_var(vars.counter, zeros);
+    repeat(b, vars.counter, |b| write_str(b, vars, "0"));
+
+    let digits = b.use_var(vars.digits);
+    b.def_var(vars.counter, digits);
+    repeat(b, vars.counter, |b| write_digit(b, vars));
+}
+
+/// E.g. 1500 or 1.5 for exponents from 0 to 14
+fn write_fixed(b: &mut FunctionBuilder, vars: Vars) {
+    let exp = b.use_var(vars.exp);
+    let digits = b.use_var(vars.digits);
+    let integer_digits = b.ins().iadd_imm(exp, 1);
+
+    // The integer part may end with zeros which are not significant digits
+    let significant = b.ins().smin(integer_digits, digits);
+    let zeros = b.ins().isub(integer_digits, significant);
+
+    b.def_var(vars.counter, significant);
+    repeat(b, vars.counter, |b| write_digit(b, vars));
+
+    b.def_var(vars.counter, zeros);
+    repeat(b, vars.counter, |b| write_str(b, vars, "0"));
+
+    let fraction = b.ins().isub(digits, significant);
+    b.def_var(vars.counter, fraction);
+
+    if_then(b, fraction, |b| write_str(b, vars, "."));
+    repeat(b, vars.counter, |b| write_digit(b, vars));
+}
+
+/// Writes the next significant digit
+fn write_digit(b: &mut FunctionBuilder, vars: Vars) {
+    let mantissa = b.use_var(vars.mantissa);
+    let divisor = b.use_var(vars.divisor);
+
+    let digit = b.ins().sdiv(mantissa, divisor);
+    let rest = b.ins().srem(mantissa, divisor);
+    let next_divisor = b.ins().sdiv_imm(divisor, 10);
+
+    b.def_var(vars.mantissa, rest);
+    b.def_var(vars.divisor, next_divisor);
+
+    write_char(b, vars, digit);
+}
+
+/// Writes a digit from 0 to 9
+fn write_char(b: &mut FunctionBuilder, vars: Vars, digit: Value) {
+    let char = b.ins().iadd_imm(digit, '0' as i64);
+    let char = b.ins().ireduce(types::I8, char);
+
+    let position = b.use_var(vars.position);
+    b.ins().store(MemFlags::trusted(), char, position, 0);
+    increment(b, vars.position, 1);
+}
+
+fn write_str(b: &mut FunctionBuilder, vars: Vars, str: &str) {
+    for char in str.bytes() {
+        let char = b.ins().iconst(types::I8, char as i64);
+        let position = b.use_var(vars.position);
+        b.ins().store(MemFlags::trusted(), char, position, 0);
+        increment(b, vars.position, 1);
+    }
+}
+
+/// Applies the operation to the value and a constant, as there are no immediate float operations
+fn float_op<F>(b: &mut FunctionBuilder, x: Value, constant: f64, op: F) -> Value
+    where F: FnOnce(&mut FunctionBuilder, Value, Value) -> Value
+{
+    let constant = b.ins().f64const(constant);
+    op(b, x, constant)
+}
+
+fn increment(b: &mut FunctionBuilder, var: Variable, by: i64) {
+    let value = b.use_var(var);
+    let value = b.ins().iadd_imm(value, by);
+    b.def_var(var, value);
+}
+
+fn if_then<F>(b: &mut FunctionBuilder, condition: Value, then: F)
+    where F: FnOnce(&mut FunctionBuilder)
+{
+    if_else(b, condition, then, |_| ())
+}
+
+fn if_else<T, E>(b: &mut FunctionBuilder, condition: Value, then: T, otherwise: E)
+    where T: FnOnce(&mut FunctionBuilder), E: FnOnce(&mut FunctionBuilder)
+{
+    let then_block = b.create_block();
+    let else_block = b.create_block();
+    let merge_block = b.create_block();
+
+    b.ins().brif(condition, then_block, &[], else_block, &[]);
+
+    b.switch_to_block(then_block);
+    then(b);
+    b.ins().jump(merge_block, &[]);
+
+    b.switch_to_block(else_block);
+    otherwise(b);
+    b.ins().jump(merge_block, &[]);
+
+    b.switch_to_block(merge_block);
+}
+
+fn while_loop<C, B>(b: &mut FunctionBuilder, condition: C, body: B)
+    where C: FnOnce(&mut FunctionBuilder) -> Value, B: FnOnce(&mut FunctionBuilder)
+{
+    let header_block = b.create_block();
+    let body_block = b.create_block();
+    let exit_block = b.create_block();
+
+    b.ins().jump(header_block, &[]);
+
+    b.switch_to_block(header_block);
+    let condition = condition(b);
+    b.ins().brif(condition, body_block, &[], exit_block, &[]);
+
+    b.switch_to_block(body_block);
+    body(b);
+    b.ins().jump(header_block, &[]);
+
+    b.switch_to_block(exit_block);
+}
+
+/// Runs the body as often as the counter says, counting it down to zero
+fn repeat<B>(b: &mut FunctionBuilder, counter: Variable, body: B)
+    where B: FnOnce(&mut FunctionBuilder)
+{
+    while_loop(b, |b| {
+        let counter = b.use_var(counter);
+        b.ins().icmp_imm(IntCC::SignedGreaterThan, counter, 0)
+    }, |b| {
+        body(b);
+        increment(b, counter, -1);
+    });
+}
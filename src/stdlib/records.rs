@@ -0,0 +1,82 @@
+use cranelift::prelude::*;
+use cranelift_module::Module;
+
+use crate::{parser::{node::Node, types::record::{Record, RecordWord}}, codegen::function_translator::FunctionTranslator, error::Error, match_nodes};
+
+use super::functions::CodeTransformation;
+
+/// Translates the words generated for a record, see `Record::words`
+pub struct RecordWords {
+    record: Record
+}
+
+impl RecordWords {
+    pub fn new(record: Record) -> Self {
+        Self { record }
+    }
+
+    fn allocate<M: Module>(&self, translator: &mut FunctionTranslator<'_, M>, builder: &mut FunctionBuilder) -> Result<Value, Error> {
+        let size = builder.ins().iconst(types::I64, self.record.size() as i64);
+        translator.push_value(size);
+        translator.ins_call("malloc", 1, builder)?;
+
+        Ok(translator.pop_value())
+    }
+}
+
+impl<M: Module> CodeTransformation<M> for RecordWords {
+    fn try_apply<'b>(
+        &self,
+        nodes: &mut Vec<Node>,
+        translator: &mut FunctionTranslator<'b, M>,
+        builder: &mut FunctionBuilder
+    ) -> Result<bool, Error> {
+        match_nodes!(
+            nodes: [Node::Call { name, returns, .. }, ..] if self.record.word(name).is_some() => {
+                nodes.remove(0);
+
+                let flags = MemFlags::trusted();
+
+                match self.record.word(name).unwrap() {
+                    RecordWord::Constructor => {
+                        let record = self.allocate(translator, builder)?;
+
+                        // The first field is on top of the stack
+                        for i in 0..self.record.fields.len() {
+                            let field = translator.pop_value();
+                            builder.ins().store(flags, field, record, (i * 8) as i32);
+                        }
+
+                        translator.push_value(record);
+                    },
+
+                    RecordWord::Getter(i) => {
+                        let record = translator.pop_value();
+
+                        // The field of a generic record has the type this call was checked with
+                        let typ = returns[0].clone().into();
+                        let field = builder.ins().load(typ, flags, record, (i * 8) as i32);
+
+                        translator.push_value(field);
+                    },
+
+                    RecordWord::Updater(i) => {
+                        let record = translator.pop_value();
+                        let field = translator.pop_value();
+
+                        // Records are never changed in place, as other values may still point to them
+                        let copy = self.allocate(translator, builder)?;
+
+                        let size = builder.ins().iconst(types::I64, self.record.size() as i64);
+                        let config = translator.codegen.module.target_config();
+                        builder.call_memcpy(config, copy, record, size);
+
+                        builder.ins().store(flags, field, copy, (i * 8) as i32);
+
+                        translator.push_value(copy);
+                    }
+                }
+            }
+        )
+    }
+}
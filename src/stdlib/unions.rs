@@ -0,0 +1,51 @@
+use cranelift::prelude::*;
+use cranelift_module::Module;
+
+use crate::{parser::{node::Node, types::tagged_union::Union}, codegen::function_translator::FunctionTranslator, error::Error, match_nodes};
+
+use super::functions::CodeTransformation;
+
+/// Translates the constructors of a union, see `Union::words`
+pub struct UnionConstructors {
+    union: Union
+}
+
+impl UnionConstructors {
+    pub fn new(union: Union) -> Self {
+        Self { union }
+    }
+}
+
+impl<M: Module> CodeTransformation<M> for UnionConstructors {
+    fn try_apply<'b>(
+        &self,
+        nodes: &mut Vec<Node>,
+        translator: &mut FunctionTranslator<'b, M>,
+        builder: &mut FunctionBuilder
+    ) -> Result<bool, Error> {
+        match_nodes!(
+            nodes: [Node::Call { name, .. }, ..] if self.union.tag(name).is_some() => {
+                nodes.remove(0);
+
+                let tag = self.union.tag(name).unwrap();
+                let flags = MemFlags::trusted();
+
+                let size = builder.ins().iconst(types::I64, self.union.size(tag) as i64);
+                translator.push_value(size);
+                translator.ins_call("malloc", 1, builder)?;
+                let value = translator.pop_value();
+
+                let tag_value = builder.ins().iconst(types::I64, tag as i64);
+                builder.ins().store(flags, tag_value, value, 0);
+
+                // The first value of the payload is on top of the stack
+                for i in 0..self.union.payload(tag).len() {
+                    let field = translator.pop_value();
+                    builder.ins().store(flags, field, value, (i as i32 + 1) * 8);
+                }
+
+                translator.push_value(value);
+            }
+        )
+    }
+}